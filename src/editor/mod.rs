@@ -0,0 +1,632 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use std::collections::HashMap;
+
+use crate::laz::env::LazEnv;
+use crate::laz::types::LazValue;
+use crate::laz::nodes::{ID, OutputID, InputID, IODescription};
+use crate::laz::history::History;
+use crate::laz::registry::NodeRegistry;
+
+mod font;
+
+// Sizes are in pixels
+const NODE_WIDTH: f32 = 160.;
+const HEADER_HEIGHT: f32 = 22.;
+const PORT_SPACING: f32 = 16.;
+const PORT_RADIUS: f32 = 4.;
+// How far from a port a click still counts as hitting it
+const PORT_HIT_RADIUS: f32 = 8.;
+const NODE_RADIUS: f32 = 8.;
+const NODE_OUTLINE: f32 = 2.;
+const TEXT_SCALE: f32 = 2.;
+
+const MARGIN: f32 = 30.;
+const COLUMN_SPACING: f32 = 60.;
+const ROW_SPACING: f32 = 30.;
+
+// Below the outline of the section
+const PROGRESS_BAR_TOP: f32 = 14.;
+const PROGRESS_BAR_HEIGHT: f32 = 6.;
+
+// The profile overlay, in the top right corner
+const PROFILE_ROWS: usize = 8;
+const PROFILE_PADDING: f32 = 8.;
+const PROFILE_LINE_SPACING: f32 = 14.;
+
+const WIRE_WIDTH: f32 = 2.;
+const WIRE_SEGMENTS: usize = 24;
+
+// RGB
+const NODE_COLOR: [f32; 3] = [0.25, 0.22, 0.35];
+const SELECTED_COLOR: [f32; 3] = [0.55, 0.35, 0.75];
+const FAILED_COLOR: [f32; 3] = [0.65, 0.2, 0.2];
+const INPUT_COLOR: [f32; 3] = [0.85, 0.75, 0.3];
+const OUTPUT_COLOR: [f32; 3] = [0.3, 0.75, 0.85];
+const TEXT_COLOR: [f32; 3] = [0.95, 0.95, 0.95];
+const WIRE_COLOR: [f32; 3] = [0.8, 0.8, 0.8];
+const DRAG_WIRE_COLOR: [f32; 3] = [1.0, 0.8, 0.4];
+const PROGRESS_COLOR: [f32; 3] = [0.4, 0.85, 0.5];
+
+/// A rounded box, drawn by graph_box.vert. Position and size are in pixels from the top left of
+/// the window
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BoxInstance {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 3],
+    pub radius: f32,
+    pub outline: f32,
+}
+
+/// Wires are drawn as a triangle list of these
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct WireVertex {
+    pub pos: [f32; 2],
+    pub color: [f32; 3],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphGeometry {
+    pub boxes: Vec<BoxInstance>,
+    pub wires: Vec<WireVertex>,
+}
+
+impl GraphGeometry {
+    fn add_box(&mut self, pos: (f32, f32), size: (f32, f32), color: [f32; 3], radius: f32, outline: f32) {
+        self.boxes.push(BoxInstance {
+            pos: [pos.0, pos.1],
+            size: [size.0, size.1],
+            color,
+            radius,
+            outline,
+        });
+    }
+
+    /// A bar along the top of the section, filled to `fraction`
+    pub fn add_progress_bar(&mut self, origin: (f32, f32), width: f32, fraction: f32) {
+        let pos = (origin.0 + MARGIN, origin.1 + PROGRESS_BAR_TOP);
+        let size = (width - 2. * MARGIN, PROGRESS_BAR_HEIGHT);
+        self.add_box(pos, size, NODE_COLOR, PROGRESS_BAR_HEIGHT / 2., 1.);
+        self.add_box(pos, (size.0 * fraction.clamp(0., 1.), size.1), PROGRESS_COLOR, PROGRESS_BAR_HEIGHT / 2., 0.);
+    }
+
+    /// A table of the nodes that took the longest to evaluate, with their cache hit rates
+    pub fn add_profile(&mut self, env: &LazEnv, origin: (f32, f32), width: f32) {
+        let mut lines = vec![
+            format!("PROFILE, CACHE HITS {}", percent(env.cache_hit_rate())),
+            format!("{:<5} {:<12} {:>9} {:>8} {:>5} {:>8} {:>4} {:>4}", "NODE", "KIND", "TOTAL", "MEAN", "RUNS", "OUTPUT", "HIT", "OWN"),
+        ];
+        for (id, profile) in env.slowest_nodes().into_iter().take(PROFILE_ROWS) {
+            let kind = env.get_node(id).map(|node| node.kind()).unwrap_or("?");
+            lines.push(format!(
+                "{:<5} {:<12} {:>9} {:>8} {:>5} {:>8} {:>4} {:>4}",
+                id.to_string(),
+                kind.chars().take(12).collect::<String>(),
+                milliseconds(profile.total_time),
+                milliseconds(profile.mean_time()),
+                profile.runs,
+                size(profile.output_size),
+                percent(profile.cache_hit_rate()),
+                percent(profile.node_cache.hit_rate()),
+            ));
+        }
+
+        let panel_width = lines.iter().map(|line| text_width(line)).fold(0., f32::max) + 2. * PROFILE_PADDING;
+        let panel_height = lines.len() as f32 * PROFILE_LINE_SPACING + 2. * PROFILE_PADDING - (PROFILE_LINE_SPACING - text_height());
+        let pos = (origin.0 + width - MARGIN - panel_width, origin.1 + MARGIN);
+        self.add_box(pos, (panel_width, panel_height), NODE_COLOR, NODE_RADIUS, NODE_OUTLINE);
+        for (i, line) in lines.iter().enumerate() {
+            let line_pos = (pos.0 + PROFILE_PADDING, pos.1 + PROFILE_PADDING + i as f32 * PROFILE_LINE_SPACING);
+            self.add_text(line_pos, line, TEXT_COLOR);
+        }
+    }
+
+    fn add_circle(&mut self, center: (f32, f32), radius: f32, color: [f32; 3]) {
+        self.add_box((center.0 - radius, center.1 - radius), (2. * radius, 2. * radius), color, radius, 1.);
+    }
+
+    // Every lit pixel of the font is a small box
+    fn add_text(&mut self, pos: (f32, f32), text: &str, color: [f32; 3]) {
+        for (x, y) in font::text_pixels(text) {
+            let pixel = (pos.0 + x as f32 * TEXT_SCALE, pos.1 + y as f32 * TEXT_SCALE);
+            self.add_box(pixel, (TEXT_SCALE, TEXT_SCALE), color, 0., 0.);
+        }
+    }
+
+    /// Cubic bezier with horizontal tangents at both ends
+    fn add_wire(&mut self, start: (f32, f32), end: (f32, f32), color: [f32; 3]) {
+        let bend = ((end.0 - start.0).abs() / 2.).max(40.);
+        let c1 = (start.0 + bend, start.1);
+        let c2 = (end.0 - bend, end.1);
+
+        let point = |t: f32| {
+            let s = 1. - t;
+            (
+                s * s * s * start.0 + 3. * s * s * t * c1.0 + 3. * s * t * t * c2.0 + t * t * t * end.0,
+                s * s * s * start.1 + 3. * s * s * t * c1.1 + 3. * s * t * t * c2.1 + t * t * t * end.1,
+            )
+        };
+
+        let mut last = start;
+        for i in 1..=WIRE_SEGMENTS {
+            let next = point(i as f32 / WIRE_SEGMENTS as f32);
+            self.add_segment(last, next, color);
+            last = next;
+        }
+    }
+
+    fn add_segment(&mut self, a: (f32, f32), b: (f32, f32), color: [f32; 3]) {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0. {
+            return;
+        }
+        let (nx, ny) = (-dy / len * WIRE_WIDTH / 2., dx / len * WIRE_WIDTH / 2.);
+
+        let corners = [(a.0 + nx, a.1 + ny), (b.0 + nx, b.1 + ny), (b.0 - nx, b.1 - ny), (a.0 - nx, a.1 - ny)];
+        for &j in &[0, 1, 2, 2, 3, 0] {
+            self.wires.push(WireVertex { pos: [corners[j].0, corners[j].1], color });
+        }
+    }
+}
+
+fn text_width(text: &str) -> f32 {
+    font::text_width(text) as f32 * TEXT_SCALE
+}
+
+fn text_height() -> f32 {
+    font::GLYPH_HEIGHT as f32 * TEXT_SCALE
+}
+
+// Cuts off the text so it's at most max_width wide
+fn fit(text: &str, max_width: f32) -> String {
+    let max_chars = (max_width / text_width("x")).max(0.) as usize;
+    text.chars().take(max_chars).collect()
+}
+
+fn milliseconds(duration: std::time::Duration) -> String {
+    format!("{:.1}MS", duration.as_secs_f64() * 1000.)
+}
+
+fn percent(rate: Option<f64>) -> String {
+    rate.map(|rate| format!("{:.0}%", rate * 100.)).unwrap_or_else(|| "-".into())
+}
+
+fn size(bytes: usize) -> String {
+    match bytes {
+        0..=9999 => format!("{}B", bytes),
+        10000..=9999999 => format!("{}KB", bytes / 1000),
+        _ => format!("{}MB", bytes / 1000000),
+    }
+}
+
+fn node_size(io: &IODescription) -> (f32, f32) {
+    let rows = io.inputs.len().max(io.outputs.len()).max(1);
+    (NODE_WIDTH, HEADER_HEIGHT + rows as f32 * PORT_SPACING + PORT_SPACING / 2.)
+}
+
+fn input_pos(node_pos: (f32, f32), inport: usize) -> (f32, f32) {
+    (node_pos.0, node_pos.1 + HEADER_HEIGHT + PORT_SPACING * (inport as f32 + 0.5))
+}
+
+fn output_pos(node_pos: (f32, f32), outport: usize) -> (f32, f32) {
+    (node_pos.0 + NODE_WIDTH, node_pos.1 + HEADER_HEIGHT + PORT_SPACING * (outport as f32 + 0.5))
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+// Longest chain of inputs leading to a node. Cycles are cut off where they're found
+fn depth(env: &LazEnv, id: ID, depths: &mut HashMap<ID, usize>) -> usize {
+    if let Some(&depth) = depths.get(&id) {
+        return depth;
+    }
+    depths.insert(id, 0);
+
+    let inputs = env.get_node(id)
+        .map(|node| node.inputs().into_iter().map(|input| input.node).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let result = inputs.into_iter()
+        .filter(|&input| env.get_node(input).is_some())
+        .map(|input| depth(env, input, depths) + 1)
+        .max()
+        .unwrap_or(0);
+
+    depths.insert(id, result);
+    result
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Drag {
+    Node { id: ID, offset: (f32, f32) },
+    Wire { from: OutputID },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Hit {
+    Node(ID),
+    Input(InputID),
+    Output(OutputID),
+}
+
+/// The node graph editor in the menu section. Positions are in pixels relative to the top left of
+/// the section. All changes to the graph go through `history`, so they can be undone
+#[derive(Default)]
+pub struct Editor {
+    positions: HashMap<ID, (f32, f32)>,
+    pub history: History,
+    drag: Option<Drag>,
+    mouse: (f32, f32),
+    // The node that caused the last evaluation error
+    failed: Option<ID>,
+}
+
+impl std::fmt::Debug for Editor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Editor")
+            .field("positions", &self.positions)
+            .field("can_undo", &self.history.can_undo())
+            .field("can_redo", &self.history.can_redo())
+            .field("drag", &self.drag)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl Editor {
+    /// Places nodes that don't have a position yet in columns by their depth in the graph.
+    /// Positions of removed nodes are kept, so they come back in the same place on undo
+    pub fn layout(&mut self, env: &LazEnv) {
+        let ids = env.ids();
+        let mut depths = HashMap::new();
+
+        for &id in &ids {
+            if self.positions.contains_key(&id) {
+                continue;
+            }
+
+            let x = MARGIN + depth(env, id, &mut depths) as f32 * (NODE_WIDTH + COLUMN_SPACING);
+
+            // Below the other nodes in the same column
+            let y = ids.iter()
+                .filter_map(|other| {
+                    let &(other_x, other_y) = self.positions.get(other)?;
+                    let node = env.get_node(*other)?;
+                    if (other_x - x).abs() < NODE_WIDTH {
+                        Some(other_y + node_size(&node.io_description()).1 + ROW_SPACING)
+                    } else {
+                        None
+                    }
+                })
+                .fold(MARGIN, f32::max);
+
+            self.positions.insert(id, (x, y));
+        }
+    }
+
+    fn output_port_pos(&self, env: &LazEnv, output: OutputID) -> Option<(f32, f32)> {
+        let node = env.get_node(output.node)?;
+        if output.outport >= node.io_description().outputs.len() {
+            return None;
+        }
+        Some(output_pos(*self.positions.get(&output.node)?, output.outport))
+    }
+
+    fn hit(&self, env: &LazEnv, pos: (f32, f32)) -> Option<Hit> {
+        // Later nodes are drawn on top, so they get hit first
+        for id in env.ids().into_iter().rev() {
+            let (node, &node_pos) = match (env.get_node(id), self.positions.get(&id)) {
+                (Some(node), Some(node_pos)) => (node, node_pos),
+                _ => continue,
+            };
+            let io = node.io_description();
+
+            for inport in 0..io.inputs.len() {
+                if distance(pos, input_pos(node_pos, inport)) < PORT_HIT_RADIUS {
+                    return Some(Hit::Input(InputID { node: id, inport }));
+                }
+            }
+            for outport in 0..io.outputs.len() {
+                if distance(pos, output_pos(node_pos, outport)) < PORT_HIT_RADIUS {
+                    return Some(Hit::Output(OutputID { node: id, outport }));
+                }
+            }
+
+            let (width, height) = node_size(&io);
+            if pos.0 >= node_pos.0 && pos.0 < node_pos.0 + width && pos.1 >= node_pos.1 && pos.1 < node_pos.1 + height {
+                return Some(Hit::Node(id));
+            }
+        }
+        None
+    }
+
+    pub fn mouse_moved(&mut self, pos: (f32, f32)) {
+        self.mouse = pos;
+        if let Some(Drag::Node { id, offset }) = self.drag {
+            self.positions.insert(id, (pos.0 - offset.0, pos.1 - offset.1));
+        }
+    }
+
+    /// Pressing an output starts a wire, pressing a connected input picks up its wire, and pressing
+    /// a node selects it and starts dragging it
+    pub fn mouse_pressed(&mut self, env: &mut LazEnv, pos: (f32, f32)) {
+        self.mouse = pos;
+        match self.hit(env, pos) {
+            Some(Hit::Output(from)) => {
+                self.drag = Some(Drag::Wire { from });
+            }
+            Some(Hit::Input(to)) => {
+                let from = env.get_node(to.node)
+                    .and_then(|node| node.inputs().into_iter().nth(to.inport).cloned())
+                    .filter(|from| self.output_port_pos(env, *from).is_some());
+
+                if let Some(from) = from {
+                    match self.history.disconnect(env, to) {
+                        Ok(()) => self.drag = Some(Drag::Wire { from }),
+                        Err(e) => warn!("Could not disconnect {}: {}", to, e),
+                    }
+                }
+            }
+            Some(Hit::Node(id)) => {
+                env.set_selected(Some(id));
+                let (x, y) = self.positions[&id];
+                self.drag = Some(Drag::Node { id, offset: (pos.0 - x, pos.1 - y) });
+            }
+            None => {
+                env.set_selected(None);
+            }
+        }
+    }
+
+    /// Dropping a wire on an input connects it, anywhere else it's discarded
+    pub fn mouse_released(&mut self, env: &mut LazEnv, pos: (f32, f32)) {
+        self.mouse = pos;
+        if let Some(Drag::Wire { from }) = self.drag.take() {
+            if let Some(Hit::Input(to)) = self.hit(env, pos) {
+                if let Err(e) = self.history.connect(env, from, to) {
+                    warn!("Could not connect {} to {}: {}", from, to, e);
+                }
+            }
+        }
+    }
+
+    pub fn delete_selected(&mut self, env: &mut LazEnv) {
+        if let Some(id) = env.selected() {
+            if let Err(e) = self.history.remove_node(env, id) {
+                warn!("Could not remove {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Sets the selected Constant to the path of a file dropped on the window. With anything else
+    /// selected, a new Constant is added instead
+    pub fn drop_file(&mut self, env: &mut LazEnv, registry: &NodeRegistry, path: &std::path::Path) {
+        let value = LazValue::String(path.to_string_lossy().into_owned());
+        let constant = env.selected()
+            .filter(|&id| env.get_node(id).map(|node| node.kind() == "Constant").unwrap_or(false));
+
+        let result = match constant {
+            Some(id) => self.history.set_params(env, registry, id, &value),
+            None => registry.load("Constant", &value)
+                .and_then(|node| self.history.add_node(env, node))
+                .map(|id| env.set_selected(Some(id))),
+        };
+        if let Err(e) = result {
+            warn!("Could not add {}: {}", path.display(), e);
+        }
+    }
+
+    /// Collapses the selected node and everything it depends on into a group node, which takes
+    /// the selected node's place
+    pub fn collapse_selected(&mut self, env: &mut LazEnv, registry: &NodeRegistry) {
+        let selected = match env.selected().filter(|&id| env.get_node(id).is_some()) {
+            Some(id) => id,
+            None => return,
+        };
+
+        let mut ids = vec![selected];
+        let mut stack = vec![selected];
+        while let Some(id) = stack.pop() {
+            let inputs = env.get_node(id).map(|node| node.inputs().into_iter().map(|input| input.node).collect::<Vec<_>>());
+            for input in inputs.unwrap_or_default() {
+                if env.get_node(input).is_some() && !ids.contains(&input) {
+                    ids.push(input);
+                    stack.push(input);
+                }
+            }
+        }
+
+        // Unwrap is fine, the selected node exists
+        let name = format!("{} group", env.get_node(selected).unwrap().kind());
+        match self.history.collapse(env, registry, &ids, &name) {
+            Ok(group) => {
+                if let Some(&pos) = self.positions.get(&selected) {
+                    self.positions.insert(group, pos);
+                }
+                env.set_selected(Some(group));
+            }
+            Err(e) => warn!("Could not collapse {}: {}", selected, e),
+        }
+    }
+
+    pub fn undo(&mut self, env: &mut LazEnv) {
+        match self.history.undo(env) {
+            Ok(true) => {}
+            Ok(false) => info!("Nothing to undo"),
+            Err(e) => warn!("Could not undo: {}", e),
+        }
+    }
+
+    pub fn redo(&mut self, env: &mut LazEnv) {
+        match self.history.redo(env) {
+            Ok(true) => {}
+            Ok(false) => info!("Nothing to redo"),
+            Err(e) => warn!("Could not redo: {}", e),
+        }
+    }
+
+    /// Highlights the node an evaluation failed in, or clears the highlight
+    pub fn set_failed(&mut self, id: Option<ID>) {
+        self.failed = id;
+    }
+
+    /// What to draw, with the section's top left corner at `origin` in the window
+    pub fn geometry(&self, env: &LazEnv, origin: (f32, f32)) -> GraphGeometry {
+        let mut geometry = GraphGeometry::default();
+        let at = |(x, y): (f32, f32)| (origin.0 + x, origin.1 + y);
+
+        // Wires go under the nodes
+        for id in env.ids() {
+            let (node, &node_pos) = match (env.get_node(id), self.positions.get(&id)) {
+                (Some(node), Some(node_pos)) => (node, node_pos),
+                _ => continue,
+            };
+            for (inport, &from) in node.inputs().into_iter().enumerate() {
+                if let Some(start) = self.output_port_pos(env, from) {
+                    geometry.add_wire(at(start), at(input_pos(node_pos, inport)), WIRE_COLOR);
+                }
+            }
+        }
+        if let Some(Drag::Wire { from }) = self.drag {
+            if let Some(start) = self.output_port_pos(env, from) {
+                geometry.add_wire(at(start), at(self.mouse), DRAG_WIRE_COLOR);
+            }
+        }
+
+        for id in env.ids() {
+            let (node, &node_pos) = match (env.get_node(id), self.positions.get(&id)) {
+                (Some(node), Some(node_pos)) => (node, node_pos),
+                _ => continue,
+            };
+            let io = node.io_description();
+            let size = node_size(&io);
+
+            let color = if env.selected() == Some(id) {
+                SELECTED_COLOR
+            } else if self.failed == Some(id) {
+                FAILED_COLOR
+            } else {
+                NODE_COLOR
+            };
+            geometry.add_box(at(node_pos), size, color, NODE_RADIUS, NODE_OUTLINE);
+
+            let title = fit(node.kind(), size.0 - 2. * NODE_RADIUS);
+            let title_pos = (node_pos.0 + NODE_RADIUS, node_pos.1 + (HEADER_HEIGHT - text_height()) / 2.);
+            geometry.add_text(at(title_pos), &title, TEXT_COLOR);
+
+            // Port names share the width of the node with the names on the other side
+            let name_width = size.0 / 2. - 2. * PORT_RADIUS - 4.;
+
+            for (inport, port) in io.inputs.iter().enumerate() {
+                let pos = input_pos(node_pos, inport);
+                geometry.add_circle(at(pos), PORT_RADIUS, INPUT_COLOR);
+
+                let name = fit(&port.name, name_width);
+                geometry.add_text(at((pos.0 + 2. * PORT_RADIUS, pos.1 - text_height() / 2.)), &name, TEXT_COLOR);
+            }
+            for (outport, port) in io.outputs.iter().enumerate() {
+                let pos = output_pos(node_pos, outport);
+                geometry.add_circle(at(pos), PORT_RADIUS, OUTPUT_COLOR);
+
+                let name = fit(&port.name, name_width);
+                let name_pos = (pos.0 - 2. * PORT_RADIUS - text_width(&name), pos.1 - text_height() / 2.);
+                geometry.add_text(at(name_pos), &name, TEXT_COLOR);
+            }
+        }
+
+        geometry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::{ConstantNode, LazNode};
+    use crate::laz::byte_nodes::ReverseNode;
+
+    fn bytes(data: &[u8]) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Bytes(data.to_vec().into()) })
+    }
+
+    fn input(env: &LazEnv, to: InputID) -> OutputID {
+        *env.get_node(to.node).unwrap().inputs()[to.inport]
+    }
+
+    // Two constants, the first of them reversed
+    fn graph() -> (LazEnv, Editor, [ID; 3]) {
+        let mut env = LazEnv::default();
+        let first = env.add_node(bytes(&[1, 2])).unwrap();
+        let second = env.add_node(bytes(&[3])).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: OutputID { node: first, outport: 0 } })).unwrap();
+        let mut editor = Editor::default();
+        editor.layout(&env);
+        (env, editor, [first, second, reverse])
+    }
+
+    #[test]
+    fn layout_puts_inputs_left_of_their_users() {
+        let (_, editor, [first, second, reverse]) = graph();
+        let positions = |id| editor.positions[&id];
+        assert!(positions(first).0 < positions(reverse).0);
+        assert_eq!(positions(first).0, positions(second).0);
+        assert!(positions(first).1 != positions(second).1);
+    }
+
+    #[test]
+    fn dropping_a_wire_on_an_input_connects_it() {
+        let (mut env, mut editor, [first, second, reverse]) = graph();
+        let to = InputID { node: reverse, inport: 0 };
+
+        editor.mouse_pressed(&mut env, output_pos(editor.positions[&second], 0));
+        editor.mouse_released(&mut env, input_pos(editor.positions[&reverse], 0));
+        assert_eq!(input(&env, to), OutputID { node: second, outport: 0 });
+
+        editor.undo(&mut env);
+        assert_eq!(input(&env, to), OutputID { node: first, outport: 0 });
+    }
+
+    #[test]
+    fn picking_up_a_wire_and_dropping_it_elsewhere_disconnects_it() {
+        let (mut env, mut editor, [_, _, reverse]) = graph();
+        let to = InputID { node: reverse, inport: 0 };
+
+        editor.mouse_pressed(&mut env, input_pos(editor.positions[&reverse], 0));
+        editor.mouse_released(&mut env, (-100., -100.));
+        assert_eq!(input(&env, to), OutputID::DISCONNECTED);
+    }
+
+    #[test]
+    fn pressing_a_node_selects_it() {
+        let (mut env, mut editor, [_, second, _]) = graph();
+        let (x, y) = editor.positions[&second];
+        editor.mouse_pressed(&mut env, (x + NODE_WIDTH / 2., y + HEADER_HEIGHT / 2.));
+        assert_eq!(env.selected(), Some(second));
+
+        editor.mouse_pressed(&mut env, (-100., -100.));
+        assert_eq!(env.selected(), None);
+    }
+
+    #[test]
+    fn dropping_a_file_sets_the_selected_constant_or_adds_one() {
+        let (mut env, mut editor, [first, ..]) = graph();
+        let path = std::path::Path::new("some/file");
+        let registry = NodeRegistry::builtin();
+        let value = |env: &mut LazEnv, id| env.evaluate_node(id).unwrap()[0].as_str().map(String::from);
+
+        env.set_selected(Some(first));
+        editor.drop_file(&mut env, &registry, path);
+        assert_eq!(value(&mut env, first).as_deref(), Some("some/file"));
+
+        env.set_selected(None);
+        editor.drop_file(&mut env, &registry, path);
+        let added = env.selected().unwrap();
+        assert!(added != first);
+        assert_eq!(value(&mut env, added).as_deref(), Some("some/file"));
+    }
+}
@@ -0,0 +1,536 @@
+use crate::laz::types::{LazValue, LazType};
+use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, check_arity, input_bytes, input_usize};
+
+fn bytes_type() -> LazType {
+    LazType::array_of(LazType::Byte)
+}
+
+/// `length` bytes starting at `offset`. Doesn't copy the data, and slices of lazy arrays stay
+/// lazy, so only the slice is read
+pub struct SliceNode {
+    pub input_bytes: OutputID,
+    pub offset: OutputID,
+    pub length: OutputID,
+}
+
+impl LazNode for SliceNode {
+    fn kind(&self) -> &'static str {
+        "Slice"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.offset, &self.length ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.offset, &mut self.length ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Offset", LazType::integer()),
+                Port::new("Length", LazType::integer()),
+            ],
+            outputs: vec![ Port::new("Slice", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 3)?;
+
+        let offset = input_usize(&inputs[1])?;
+        let length = input_usize(&inputs[2])?;
+        let end = offset.saturating_add(length);
+
+        if let LazValue::Lazy(ref data) = inputs[0].1 {
+            if data.element_type() == LazType::Byte {
+                let slice = data.slice(offset..end).ok_or(
+                    LazError::IndexOutOfBounds { from: inputs[2].0, index: end, len: data.len() }
+                )?;
+                return Ok(vec![LazValue::Lazy(slice)]);
+            }
+        }
+
+        let data = input_bytes(&(inputs[0].0, inputs[0].1.clone().materialize()?))?;
+        let slice = data.slice(offset..end).ok_or(
+            LazError::IndexOutOfBounds { from: inputs[2].0, index: end, len: data.len() }
+        )?;
+        Ok(vec![LazValue::Bytes(slice)])
+    }
+
+    fn streams_inputs(&self) -> bool {
+        true
+    }
+}
+
+/// The bytes before and from `offset`, as two outputs. Like Slice, doesn't copy the data and keeps
+/// lazy arrays lazy
+pub struct SplitNode {
+    pub input_bytes: OutputID,
+    pub offset: OutputID,
+}
+
+impl LazNode for SplitNode {
+    fn kind(&self) -> &'static str {
+        "Split"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.offset ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.offset ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Offset", LazType::integer()),
+            ],
+            outputs: vec![
+                Port::new("Head", bytes_type()),
+                Port::new("Tail", bytes_type()),
+            ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 2)?;
+
+        let offset = input_usize(&inputs[1])?;
+        let out_of_bounds = |len| LazError::IndexOutOfBounds { from: inputs[1].0, index: offset, len };
+
+        if let LazValue::Lazy(ref data) = inputs[0].1 {
+            if data.element_type() == LazType::Byte {
+                let head = data.slice(0..offset).ok_or_else(|| out_of_bounds(data.len()))?;
+                // Unwrap is fine, the offset is in bounds
+                let tail = data.slice(offset..data.len()).unwrap();
+                return Ok(vec![LazValue::Lazy(head), LazValue::Lazy(tail)]);
+            }
+        }
+
+        let data = input_bytes(&(inputs[0].0, inputs[0].1.clone().materialize()?))?;
+        let head = data.slice(0..offset).ok_or_else(|| out_of_bounds(data.len()))?;
+        // Unwrap is fine, the offset is in bounds
+        let tail = data.slice(offset..data.len()).unwrap();
+        Ok(vec![LazValue::Bytes(head), LazValue::Bytes(tail)])
+    }
+
+    fn streams_inputs(&self) -> bool {
+        true
+    }
+}
+
+pub struct ConcatNode {
+    pub first: OutputID,
+    pub second: OutputID,
+}
+
+impl LazNode for ConcatNode {
+    fn kind(&self) -> &'static str {
+        "Concat"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.first, &self.second ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.first, &mut self.second ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("First", bytes_type()),
+                Port::new("Second", bytes_type()),
+            ],
+            outputs: vec![ Port::new("Concatenated", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 2)?;
+
+        let first = input_bytes(&inputs[0])?;
+        let second = input_bytes(&inputs[1])?;
+
+        let mut out = Vec::with_capacity(first.len() + second.len());
+        out.extend_from_slice(&first);
+        out.extend_from_slice(&second);
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Xors the input with a key, repeating the key as needed
+pub struct XorNode {
+    pub input_bytes: OutputID,
+    pub key: OutputID,
+}
+
+impl LazNode for XorNode {
+    fn kind(&self) -> &'static str {
+        "Xor"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.key ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.key ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Key", bytes_type()),
+            ],
+            outputs: vec![ Port::new("Xored", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 2)?;
+
+        let data = input_bytes(&inputs[0])?;
+        let key = input_bytes(&inputs[1])?;
+        if key.is_empty() {
+            return Err(LazError::InvalidInputValue { from: inputs[1].0, reason: "Empty key".into() });
+        }
+
+        let out = data.iter().zip(key.iter().cycle()).map(|(&x, &k)| x ^ k).collect::<Vec<_>>();
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Every `stride`th byte, starting at `offset`. Deinterleaves data with `stride` channels
+pub struct StrideNode {
+    pub input_bytes: OutputID,
+    pub stride: OutputID,
+    pub offset: OutputID,
+}
+
+impl LazNode for StrideNode {
+    fn kind(&self) -> &'static str {
+        "Stride"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.stride, &self.offset ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.stride, &mut self.offset ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Stride", LazType::integer()),
+                Port::new("Offset", LazType::integer()),
+            ],
+            outputs: vec![ Port::new("Strided", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 3)?;
+
+        let data = input_bytes(&inputs[0])?;
+        let stride = input_usize(&inputs[1])?;
+        let offset = input_usize(&inputs[2])?;
+        if stride == 0 {
+            return Err(LazError::InvalidInputValue { from: inputs[1].0, reason: "Stride must be positive".into() });
+        }
+
+        let out = data.iter().skip(offset).step_by(stride).cloned().collect::<Vec<_>>();
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+pub struct ReverseNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for ReverseNode {
+    fn kind(&self) -> &'static str {
+        "Reverse"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Reversed", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        let out = data.iter().rev().cloned().collect::<Vec<_>>();
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Reverses the byte order of each `width` byte word. A trailing partial word is left as is
+pub struct EndianSwapNode {
+    pub input_bytes: OutputID,
+    pub width: OutputID,
+}
+
+impl LazNode for EndianSwapNode {
+    fn kind(&self) -> &'static str {
+        "EndianSwap"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.width ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.width ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Word width", LazType::integer()),
+            ],
+            outputs: vec![ Port::new("Swapped", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 2)?;
+
+        let data = input_bytes(&inputs[0])?;
+        let width = input_usize(&inputs[1])?;
+        if width == 0 {
+            return Err(LazError::InvalidInputValue { from: inputs[1].0, reason: "Word width must be positive".into() });
+        }
+
+        let mut out = data.to_vec();
+        for word in out.chunks_exact_mut(width) {
+            word.reverse();
+        }
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Splits the input into an array of `size` byte chunks. The last chunk may be shorter
+pub struct ChunkNode {
+    pub input_bytes: OutputID,
+    pub size: OutputID,
+}
+
+impl LazNode for ChunkNode {
+    fn kind(&self) -> &'static str {
+        "Chunk"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes, &self.size ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes, &mut self.size ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![
+                Port::new("Bytes", bytes_type()),
+                Port::new("Chunk size", LazType::integer()),
+            ],
+            outputs: vec![ Port::new("Chunks", LazType::array_of(bytes_type())) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 2)?;
+
+        let data = input_bytes(&inputs[0])?;
+        let size = input_usize(&inputs[1])?;
+        if size == 0 {
+            return Err(LazError::InvalidInputValue { from: inputs[1].0, reason: "Chunk size must be positive".into() });
+        }
+
+        let chunks = (0..data.len()).step_by(size)
+            .map(|start| {
+                let end = (start + size).min(data.len());
+                // Unwrap is fine, start..end is within data
+                LazValue::Bytes(data.slice(start..end).unwrap())
+            })
+            .collect::<Vec<_>>();
+        Ok(vec![LazValue::Array(chunks)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Range;
+    use std::sync::Arc;
+    use crate::laz::nodes::{ID, assert_checks_arity};
+    use crate::laz::types::{ArraySource, LazyArray};
+
+    /// Serves 0, 1, 2, ... as bytes
+    struct Counting(usize);
+
+    impl ArraySource for Counting {
+        fn len(&self) -> usize {
+            self.0
+        }
+        fn element_type(&self) -> LazType {
+            LazType::Byte
+        }
+        fn read(&self, range: Range<usize>) -> Result<LazValue, LazError> {
+            Ok(LazValue::Bytes(range.map(|x| x as u8).collect::<Vec<_>>().into()))
+        }
+    }
+
+    fn input(node: u64, value: LazValue) -> (OutputID, LazValue) {
+        (OutputID { node: ID(node), outport: 0 }, value)
+    }
+
+    fn bytes(data: &[u8]) -> LazValue {
+        LazValue::Bytes(data.to_vec().into())
+    }
+
+    fn run(node: &mut dyn LazNode, values: Vec<LazValue>) -> Result<Vec<LazValue>, LazError> {
+        let inputs = values.into_iter().enumerate().map(|(i, value)| input(i as u64, value)).collect();
+        node.evaluate_for(inputs)
+    }
+
+    fn output_bytes(value: &LazValue) -> Vec<u8> {
+        value.clone().materialize().unwrap().as_bytes().unwrap().to_vec()
+    }
+
+    fn run_bytes(node: &mut dyn LazNode, values: Vec<LazValue>) -> Vec<u8> {
+        output_bytes(&run(node, values).unwrap()[0])
+    }
+
+    fn disconnected<const N: usize>() -> [OutputID; N] {
+        [OutputID::DISCONNECTED; N]
+    }
+
+    #[test]
+    fn slice() {
+        let [input_bytes, offset, length] = disconnected();
+        let mut node = SliceNode { input_bytes, offset, length };
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[1, 2, 3, 4, 5]), 1u64.into(), 3u64.into()]), [2, 3, 4]);
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[1, 2, 3]), 3u64.into(), 0u64.into()]), []);
+
+        let err = run(&mut node, vec![bytes(&[1, 2, 3]), 2u64.into(), 2u64.into()]).unwrap_err();
+        assert!(matches!(err, LazError::IndexOutOfBounds { index: 4, len: 3, .. }));
+        let err = run(&mut node, vec![bytes(&[1]), u64::MAX.into(), u64::MAX.into()]).unwrap_err();
+        assert!(matches!(err, LazError::IndexOutOfBounds { .. }));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn slice_of_lazy_array_stays_lazy() {
+        let [input_bytes, offset, length] = disconnected();
+        let mut node = SliceNode { input_bytes, offset, length };
+        let lazy = LazValue::Lazy(LazyArray::new(Arc::new(Counting(10))));
+
+        let out = run(&mut node, vec![lazy.clone(), 4u64.into(), 3u64.into()]).unwrap();
+        assert!(matches!(out[0], LazValue::Lazy(_)));
+        assert_eq!(output_bytes(&out[0]), [4, 5, 6]);
+
+        let err = run(&mut node, vec![lazy, 8u64.into(), 3u64.into()]).unwrap_err();
+        assert!(matches!(err, LazError::IndexOutOfBounds { index: 11, len: 10, .. }));
+    }
+
+    #[test]
+    fn split() {
+        let [input_bytes, offset] = disconnected();
+        let mut node = SplitNode { input_bytes, offset };
+        let out = run(&mut node, vec![bytes(&[1, 2, 3, 4]), 1u64.into()]).unwrap();
+        assert_eq!(output_bytes(&out[0]), [1]);
+        assert_eq!(output_bytes(&out[1]), [2, 3, 4]);
+
+        let out = run(&mut node, vec![bytes(&[1, 2]), 2u64.into()]).unwrap();
+        assert_eq!(output_bytes(&out[0]), [1, 2]);
+        assert_eq!(output_bytes(&out[1]), []);
+
+        let lazy = LazValue::Lazy(LazyArray::new(Arc::new(Counting(5))));
+        let out = run(&mut node, vec![lazy.clone(), 2u64.into()]).unwrap();
+        assert!(matches!(out[1], LazValue::Lazy(_)));
+        assert_eq!(output_bytes(&out[0]), [0, 1]);
+        assert_eq!(output_bytes(&out[1]), [2, 3, 4]);
+
+        let err = run(&mut node, vec![lazy, 6u64.into()]).unwrap_err();
+        assert!(matches!(err, LazError::IndexOutOfBounds { index: 6, len: 5, .. }));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn concat() {
+        let [first, second] = disconnected();
+        let mut node = ConcatNode { first, second };
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[1, 2]), bytes(&[3])]), [1, 2, 3]);
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[]), bytes(&[])]), []);
+        assert!(run(&mut node, vec![bytes(&[1]), 1u64.into()]).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn xor_repeats_the_key() {
+        let [input_bytes, key] = disconnected();
+        let mut node = XorNode { input_bytes, key };
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[0, 0, 0, 0xff, 0x0f]), bytes(&[1, 2])]), [1, 2, 1, 0xfd, 0x0e]);
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[]), bytes(&[1])]), []);
+
+        let err = run(&mut node, vec![bytes(&[1]), bytes(&[])]).unwrap_err();
+        assert!(matches!(err, LazError::InvalidInputValue { from, .. } if from.node == ID(1)));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn stride() {
+        let [input_bytes, stride, offset] = disconnected();
+        let mut node = StrideNode { input_bytes, stride, offset };
+        let data = bytes(&[0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 3u64.into(), 0u64.into()]), [0, 3, 6]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 2u64.into(), 1u64.into()]), [1, 3, 5]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 1u64.into(), 10u64.into()]), []);
+        assert!(matches!(
+            run(&mut node, vec![data, 0u64.into(), 0u64.into()]).unwrap_err(),
+            LazError::InvalidInputValue { .. }
+        ));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn reverse() {
+        let [input_bytes] = disconnected();
+        let mut node = ReverseNode { input_bytes };
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[1, 2, 3])]), [3, 2, 1]);
+        assert_eq!(run_bytes(&mut node, vec![bytes(&[])]), []);
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn endian_swap_leaves_partial_word() {
+        let [input_bytes, width] = disconnected();
+        let mut node = EndianSwapNode { input_bytes, width };
+        let data = bytes(&[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 2u64.into()]), [2, 1, 4, 3, 6, 5, 7]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 4u64.into()]), [4, 3, 2, 1, 5, 6, 7]);
+        assert_eq!(run_bytes(&mut node, vec![data.clone(), 1u64.into()]), [1, 2, 3, 4, 5, 6, 7]);
+        assert!(run(&mut node, vec![data, 0u64.into()]).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn chunk() {
+        let [input_bytes, size] = disconnected();
+        let mut node = ChunkNode { input_bytes, size };
+        let out = run(&mut node, vec![bytes(&[1, 2, 3, 4, 5]), 2u64.into()]).unwrap();
+        let chunks = match &out[0] {
+            LazValue::Array(chunks) => chunks.iter().map(output_bytes).collect::<Vec<_>>(),
+            x => panic!("Expected an array, got {:?}", x),
+        };
+        assert_eq!(chunks, vec![vec![1, 2], vec![3, 4], vec![5]]);
+
+        let out = run(&mut node, vec![bytes(&[]), 2u64.into()]).unwrap();
+        assert!(matches!(out[0], LazValue::Array(ref chunks) if chunks.is_empty()));
+        assert!(run(&mut node, vec![bytes(&[1]), 0u64.into()]).is_err());
+        assert_checks_arity(&mut node);
+    }
+}
@@ -0,0 +1,341 @@
+use std::io::Read;
+
+use crate::laz::types::{LazValue, LazType, LazyArray};
+use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, check_arity, Encoding, input_bytes};
+use crate::laz::env::EvalControl;
+
+fn bytes_type() -> LazType {
+    LazType::array_of(LazType::Byte)
+}
+
+/// Text encodings can be given either as a string or as the raw bytes of the text
+fn text_type() -> LazType {
+    LazType::Union(vec![LazType::String, bytes_type()])
+}
+
+fn input_text(input: &(OutputID, LazValue)) -> Result<Vec<u8>, LazError> {
+    if let LazValue::String(ref text) = input.1 {
+        return Ok(text.as_bytes().to_vec());
+    }
+    input.1.as_bytes()
+        .map(|bytes| bytes.to_vec())
+        .ok_or_else(|| LazError::InvalidInputType { from: input.0, expected: text_type(), actual: input.1.get_type() })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Deflate with a zlib header and checksum
+    Zlib,
+    /// Raw deflate stream
+    Deflate,
+    Gzip,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Zlib => "zlib",
+            Compression::Deflate => "deflate",
+            Compression::Gzip => "gzip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "zlib" => Some(Compression::Zlib),
+            "deflate" => Some(Compression::Deflate),
+            "gzip" => Some(Compression::Gzip),
+            _ => None,
+        }
+    }
+}
+
+pub struct DecompressNode {
+    pub input_bytes: OutputID,
+    pub compression: Compression,
+}
+
+impl LazNode for DecompressNode {
+    fn kind(&self) -> &'static str {
+        "Decompress"
+    }
+    fn params(&self) -> LazValue {
+        LazValue::Record(vec![ ("compression".into(), LazValue::String(self.compression.name().into())) ])
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Compressed", bytes_type()) ],
+            outputs: vec![ Port::new("Decompressed", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        self.evaluate_with(inputs, &EvalControl::default())
+    }
+
+    // Decompresses a chunk at a time, so decompressing a large stream can be cancelled
+    fn evaluate_with(&mut self, inputs: Vec<(OutputID, LazValue)>, control: &EvalControl) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        let (mut decoder, encoding): (Box<dyn Read>, _) = match self.compression {
+            Compression::Zlib => (Box::new(flate2::read::ZlibDecoder::new(&data[..])), Encoding::Zlib),
+            Compression::Deflate => (Box::new(flate2::read::DeflateDecoder::new(&data[..])), Encoding::Deflate),
+            Compression::Gzip => (Box::new(flate2::read::GzDecoder::new(&data[..])), Encoding::Gzip),
+        };
+
+        let mut out = Vec::new();
+        loop {
+            if control.is_cancelled() {
+                return Err(LazError::Cancelled);
+            }
+            let read = (&mut decoder).take(LazyArray::CHUNK_LEN as u64).read_to_end(&mut out)
+                .map_err(|e| LazError::DecodeFailed { from: inputs[0].0, encoding, reason: e.to_string() })?;
+            if read == 0 {
+                break;
+            }
+        }
+
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Decodes standard base64, with padding
+pub struct Base64DecodeNode {
+    pub input_text: OutputID,
+}
+
+impl LazNode for Base64DecodeNode {
+    fn kind(&self) -> &'static str {
+        "Base64Decode"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_text ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_text ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Base64", text_type()) ],
+            outputs: vec![ Port::new("Decoded", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let mut text = input_text(&inputs[0])?;
+        text.retain(|c| !c.is_ascii_whitespace());
+
+        let out = base64::decode(&text)
+            .map_err(|e| LazError::DecodeFailed { from: inputs[0].0, encoding: Encoding::Base64, reason: e.to_string() })?;
+
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+/// Decodes hex digits, two per byte. Whitespace is ignored
+pub struct HexDecodeNode {
+    pub input_text: OutputID,
+}
+
+impl LazNode for HexDecodeNode {
+    fn kind(&self) -> &'static str {
+        "HexDecode"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_text ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_text ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Hex", text_type()) ],
+            outputs: vec![ Port::new("Decoded", bytes_type()) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let mut text = input_text(&inputs[0])?;
+        text.retain(|c| !c.is_ascii_whitespace());
+
+        let fail = |reason: String| LazError::DecodeFailed { from: inputs[0].0, encoding: Encoding::Hex, reason };
+
+        if text.len() % 2 != 0 {
+            return Err(fail("Odd number of hex digits".into()));
+        }
+
+        let digit = |c: u8| (c as char).to_digit(16).ok_or_else(|| fail(format!("Invalid hex digit {:?}", c as char)));
+
+        let out = text.chunks_exact(2)
+            .map(|pair| Ok((digit(pair[0])? * 16 + digit(pair[1])?) as u8))
+            .collect::<Result<Vec<_>, LazError>>()?;
+
+        Ok(vec![LazValue::Bytes(out.into())])
+    }
+}
+
+pub struct Utf16DecodeNode {
+    pub input_bytes: OutputID,
+    pub big_endian: bool,
+}
+
+impl LazNode for Utf16DecodeNode {
+    fn kind(&self) -> &'static str {
+        "Utf16Decode"
+    }
+    fn params(&self) -> LazValue {
+        LazValue::Record(vec![ ("big_endian".into(), LazValue::Bool(self.big_endian)) ])
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("UTF-16", bytes_type()) ],
+            outputs: vec![ Port::new("Text", LazType::String) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        let encoding = if self.big_endian { Encoding::Utf16Be } else { Encoding::Utf16Le };
+        let fail = |reason: String| LazError::DecodeFailed { from: inputs[0].0, encoding, reason };
+
+        if data.len() % 2 != 0 {
+            return Err(fail("Odd number of bytes".into()));
+        }
+
+        let units = data.chunks_exact(2)
+            .map(|pair| {
+                let pair = [pair[0], pair[1]];
+                if self.big_endian { u16::from_be_bytes(pair) } else { u16::from_le_bytes(pair) }
+            })
+            .collect::<Vec<_>>();
+
+        let text = String::from_utf16(&units).map_err(|e| fail(e.to_string()))?;
+
+        Ok(vec![LazValue::String(text)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+    use crate::laz::nodes::{ID, assert_checks_arity};
+
+    fn run(node: &mut dyn LazNode, value: LazValue) -> Result<LazValue, LazError> {
+        let input = (OutputID { node: ID(0), outport: 0 }, value);
+        Ok(node.evaluate_for(vec![input])?.remove(0))
+    }
+
+    fn bytes(data: &[u8]) -> LazValue {
+        LazValue::Bytes(data.to_vec().into())
+    }
+
+    fn decoded_bytes(node: &mut dyn LazNode, value: LazValue) -> Vec<u8> {
+        run(node, value).unwrap().as_bytes().unwrap().to_vec()
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let level = flate2::Compression::default();
+        match compression {
+            Compression::Zlib => {
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Deflate => {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn decompress() {
+        let data = b"hello hello hello hello";
+        for &compression in &[Compression::Zlib, Compression::Deflate, Compression::Gzip] {
+            let mut node = DecompressNode { input_bytes: OutputID::DISCONNECTED, compression };
+            assert_eq!(decoded_bytes(&mut node, bytes(&compress(compression, data))), data);
+            assert_checks_arity(&mut node);
+        }
+
+        // Gzip data has the wrong header for zlib
+        let mut node = DecompressNode { input_bytes: OutputID::DISCONNECTED, compression: Compression::Zlib };
+        let err = run(&mut node, bytes(&compress(Compression::Gzip, data))).unwrap_err();
+        assert!(matches!(err, LazError::DecodeFailed { encoding: Encoding::Zlib, .. }));
+    }
+
+    #[test]
+    fn cancelled_decompress_fails() {
+        let data = vec![7; LazyArray::CHUNK_LEN * 3];
+        let mut node = DecompressNode { input_bytes: OutputID::DISCONNECTED, compression: Compression::Zlib };
+        let input = (OutputID { node: ID(0), outport: 0 }, bytes(&compress(Compression::Zlib, &data)));
+
+        // Output longer than a chunk is still decompressed in full
+        assert_eq!(node.evaluate_with(vec![input.clone()], &EvalControl::default()).unwrap()[0].as_bytes().unwrap()[..], data[..]);
+
+        let control = EvalControl::default();
+        control.cancel();
+        assert!(matches!(node.evaluate_with(vec![input], &control).unwrap_err(), LazError::Cancelled));
+    }
+
+    #[test]
+    fn base64_decode() {
+        let mut node = Base64DecodeNode { input_text: OutputID::DISCONNECTED };
+        assert_eq!(decoded_bytes(&mut node, LazValue::String("aGVs\nbG8=".into())), b"hello");
+        assert_eq!(decoded_bytes(&mut node, bytes(b"AAE=")), [0, 1]);
+        assert!(matches!(run(&mut node, LazValue::String("a!==".into())).unwrap_err(), LazError::DecodeFailed { .. }));
+        assert!(matches!(run(&mut node, LazValue::Byte(1)).unwrap_err(), LazError::InvalidInputType { .. }));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn hex_decode() {
+        let mut node = HexDecodeNode { input_text: OutputID::DISCONNECTED };
+        assert_eq!(decoded_bytes(&mut node, LazValue::String("00 ff\n7A".into())), [0, 0xff, 0x7a]);
+        assert_eq!(decoded_bytes(&mut node, bytes(b"")), []);
+        assert!(run(&mut node, LazValue::String("abc".into())).is_err());
+        assert!(run(&mut node, LazValue::String("zz".into())).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn utf16_decode() {
+        let mut node = Utf16DecodeNode { input_bytes: OutputID::DISCONNECTED, big_endian: false };
+        let text = run(&mut node, bytes(&[b'h', 0, b'i', 0, 0x3d, 0xd8, 0x00, 0xde])).unwrap();
+        assert_eq!(text.as_str(), Some("hi\u{1f600}"));
+        assert!(matches!(run(&mut node, bytes(b"h")).unwrap_err(), LazError::DecodeFailed { encoding: Encoding::Utf16Le, .. }));
+        // An unpaired surrogate
+        assert!(run(&mut node, bytes(&[0x3d, 0xd8])).is_err());
+        assert_checks_arity(&mut node);
+
+        let mut node = Utf16DecodeNode { input_bytes: OutputID::DISCONNECTED, big_endian: true };
+        assert_eq!(run(&mut node, bytes(&[0, b'h', 0, b'i'])).unwrap().as_str(), Some("hi"));
+    }
+}
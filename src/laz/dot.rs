@@ -0,0 +1,229 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use crate::laz::env::{LazEnv, NodeProfile};
+use crate::laz::nodes::ID;
+
+// Node colors: cached, failed in the last run, and dirty
+const CACHED_COLOR: &str = "#d5ecd4";
+const FAILED_COLOR: &str = "#f4c7c3";
+const DIRTY_COLOR: &str = "#eeeeee";
+
+// Longer errors are cut off, so nodes stay readable
+const MAX_ERROR_LEN: usize = 80;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "<br/>")
+}
+
+fn truncate(text: &str, len: usize) -> String {
+    if text.chars().count() <= len {
+        return text.into();
+    }
+    text.chars().take(len - 3).chain("...".chars()).collect()
+}
+
+// One line describing the state of the node
+fn status(env: &LazEnv, id: ID) -> (String, &'static str) {
+    let record = match env.last_evaluation(id) {
+        Some(record) => record,
+        None => return ("not evaluated".into(), DIRTY_COLOR),
+    };
+
+    let ms = record.duration.as_secs_f64() * 1000.;
+    let ago = SystemTime::now().duration_since(record.finished).map(|d| d.as_secs()).unwrap_or(0);
+    let timing = format!("{:.2} ms, {} s ago", ms, ago);
+    match (&record.error, env.is_dirty(id)) {
+        (Some(error), _) => {
+            let error = truncate(&error.root_cause().to_string(), MAX_ERROR_LEN);
+            (format!("failed ({}): {}", timing, error), FAILED_COLOR)
+        }
+        (None, false) => (format!("ok ({})", timing), CACHED_COLOR),
+        (None, true) => (format!("dirty, last ok ({})", timing), DIRTY_COLOR),
+    }
+}
+
+// Totals since the profiles were last reset
+fn profile_line(profile: &NodeProfile) -> String {
+    let hits = profile.cache_hit_rate().map(|rate| format!("{:.0}%", rate * 100.)).unwrap_or_else(|| "-".into());
+    format!("{} runs, {:.2} ms total, {} cached", profile.runs, profile.total_time.as_secs_f64() * 1000., hits)
+}
+
+/// The graph in Graphviz DOT format, for debugging and reports. Each node is a table with its
+/// ID, kind, ports, how its last evaluation went and its profile. Edges go from output to input
+/// ports
+pub fn to_dot(env: &LazEnv) -> String {
+    let mut dot = String::new();
+    // Unwrap is fine, writing to a String can't fail
+    write_dot(env, &mut dot).unwrap();
+    dot
+}
+
+fn write_dot(env: &LazEnv, dot: &mut String) -> std::fmt::Result {
+    writeln!(dot, "digraph laz {{")?;
+    writeln!(dot, "    rankdir=LR;")?;
+    writeln!(dot, "    node [shape=plaintext, fontname=\"monospace\", fontsize=10];")?;
+
+    for id in env.ids() {
+        // Unwrap is fine, the id comes from the env
+        let node = env.get_node(id).unwrap();
+        let io = node.io_description();
+        let (status, color) = status(env, id);
+
+        let mut title = format!("<b>{} {}</b><br/>{}", id, escape(node.kind()), escape(&status));
+        if let Some(profile) = env.profile(id) {
+            title.push_str(&format!("<br/>{}", escape(&profile_line(profile))));
+        }
+        if env.displayed().map(|output| output.node) == Some(id) {
+            title.push_str("<br/><i>displayed</i>");
+        }
+        let selected = if env.selected() == Some(id) { " color=\"blue\"" } else { "" };
+
+        let rows = io.inputs.len().max(io.outputs.len()).max(1);
+        writeln!(dot, "    n{} [label=<", id.0)?;
+        writeln!(dot, "        <table border=\"1\" cellborder=\"0\" cellspacing=\"0\" bgcolor=\"{}\"{}>", color, selected)?;
+        for row in 0..rows {
+            write!(dot, "            <tr>")?;
+            match io.inputs.get(row) {
+                Some(port) => write!(dot, "<td port=\"i{}\" align=\"left\">{}: {}</td>", row, escape(&port.name), escape(&port.ty.to_string()))?,
+                None => dot.push_str("<td></td>"),
+            }
+            if row == 0 {
+                write!(dot, "<td rowspan=\"{}\">{}</td>", rows, title)?;
+            }
+            match io.outputs.get(row) {
+                Some(port) => write!(dot, "<td port=\"o{}\" align=\"right\">{}: {}</td>", row, escape(&port.name), escape(&port.ty.to_string()))?,
+                None => dot.push_str("<td></td>"),
+            }
+            dot.push_str("</tr>\n");
+        }
+        writeln!(dot, "        </table>")?;
+        writeln!(dot, "    >];")?;
+    }
+
+    // Inputs that are disconnected, or connected to a removed node, have no edge
+    for id in env.ids() {
+        // Unwrap is fine, the id comes from the env
+        let node = env.get_node(id).unwrap();
+        for (inport, from) in node.inputs().into_iter().enumerate() {
+            if env.get_node(from.node).is_some() {
+                writeln!(dot, "    n{}:o{}:e -> n{}:i{}:w;", from.node.0, from.outport, id.0, inport)?;
+            }
+        }
+    }
+
+    dot.push_str("}\n");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::types::{LazValue, LazType};
+    use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, ConstantNode};
+
+    // Passes its input through twice, or fails with an error that has to be escaped
+    struct Weird {
+        input: OutputID,
+        fail: bool,
+    }
+
+    impl LazNode for Weird {
+        fn kind(&self) -> &'static str {
+            "Weird<&>"
+        }
+        fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+            vec![ &self.input ]
+        }
+        fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+            vec![ &mut self.input ]
+        }
+        fn io_description(&self) -> IODescription {
+            IODescription {
+                inputs: vec![ Port::new("In <1>", LazType::Any) ],
+                outputs: vec![ Port::new("Out", LazType::Any), Port::new("Copy", LazType::Any) ],
+            }
+        }
+
+        fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+            if self.fail {
+                return Err(LazError::Other("\"x\" < y & z".into()));
+            }
+            Ok(vec![inputs[0].1.clone(), inputs[0].1.clone()])
+        }
+    }
+
+    fn weird(from: OutputID, fail: bool) -> Box<dyn LazNode> {
+        Box::new(Weird { input: from, fail })
+    }
+
+    fn constant(value: u8) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Byte(value) })
+    }
+
+    // The table of one node
+    fn node_table(dot: &str, id: ID) -> &str {
+        let start = dot.find(&format!("n{} [label=<", id.0)).unwrap();
+        let len = dot[start..].find(">];").unwrap();
+        &dot[start..start + len]
+    }
+
+    #[test]
+    fn ports_and_edges() {
+        let mut env = LazEnv::default();
+        let c = env.add_node(constant(1)).unwrap();
+        let first = env.add_node(weird(OutputID { node: c, outport: 0 }, false)).unwrap();
+        let second = env.add_node(weird(OutputID { node: first, outport: 1 }, false)).unwrap();
+        let loose = env.add_node(weird(OutputID::DISCONNECTED, false)).unwrap();
+        let dot = to_dot(&env);
+
+        let table = node_table(&dot, first);
+        assert!(table.contains(&format!("<td port=\"i0\" align=\"left\">In &lt;1&gt;: {}</td>", LazType::Any)), "{}", table);
+        assert!(table.contains(&format!("<td port=\"o1\" align=\"right\">Copy: {}</td>", LazType::Any)), "{}", table);
+        assert!(table.contains("Weird&lt;&amp;&gt;") && !table.contains("Weird<&>"), "{}", table);
+
+        assert!(dot.contains(&format!("n{}:o0:e -> n{}:i0:w;", c.0, first.0)), "{}", dot);
+        assert!(dot.contains(&format!("n{}:o1:e -> n{}:i0:w;", first.0, second.0)), "{}", dot);
+        assert!(!dot.contains(&format!("-> n{}:", loose.0)), "{}", dot);
+        assert_eq!(dot.matches(" -> ").count(), 2);
+    }
+
+    #[test]
+    fn colors_follow_the_last_evaluation() {
+        let mut env = LazEnv::default();
+        let c = env.add_node(constant(1)).unwrap();
+        let ok = env.add_node(weird(OutputID { node: c, outport: 0 }, false)).unwrap();
+        let bad = env.add_node(weird(OutputID { node: c, outport: 0 }, true)).unwrap();
+
+        let dot = to_dot(&env);
+        for &id in &[c, ok, bad] {
+            let table = node_table(&dot, id);
+            assert!(table.contains(DIRTY_COLOR) && table.contains("not evaluated"), "{}", table);
+        }
+
+        env.evaluate_node(ok).unwrap();
+        env.evaluate_node(bad).unwrap_err();
+        let dot = to_dot(&env);
+        assert!(node_table(&dot, ok).contains(CACHED_COLOR));
+        let table = node_table(&dot, bad);
+        assert!(table.contains(FAILED_COLOR), "{}", table);
+        assert!(table.contains("&quot;x&quot; &lt; y &amp; z"), "{}", table);
+
+        env.replace_node(c, constant(2)).unwrap();
+        let table = node_table(&to_dot(&env), ok).to_string();
+        assert!(table.contains(DIRTY_COLOR) && table.contains("dirty, last ok"), "{}", table);
+    }
+
+    #[test]
+    fn long_errors_are_cut_off() {
+        assert_eq!(truncate("short", MAX_ERROR_LEN), "short");
+        let cut = truncate(&"x".repeat(200), MAX_ERROR_LEN);
+        assert_eq!(cut.chars().count(), MAX_ERROR_LEN);
+        assert!(cut.ends_with("..."));
+        assert_eq!(escape("a\nb"), "a<br/>b");
+    }
+}
@@ -1,7 +1,122 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::laz::types::LazValue;
-use crate::laz::nodes::{LazNode, ID, OutputID, InputID, LazError};
+use serde::{Serialize, Deserialize};
+
+use crate::laz::types::{LazValue, LazType};
+use crate::laz::nodes::{LazNode, ID, OutputID, InputID, LazError, CacheStats, check_arity};
+use crate::laz::registry::NodeRegistry;
+
+// The format graphs are saved in. Also embedded in group templates
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct SavedEnv {
+    nodes: Vec<SavedNode>,
+    displayed: Option<OutputID>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SavedNode {
+    id: ID,
+    kind: String,
+    params: LazValue,
+    inputs: Vec<OutputID>,
+}
+
+// The ID after `id`, unless that would overflow or be the ID of OutputID::DISCONNECTED, which no
+// node can have
+fn id_after(id: ID) -> Option<ID> {
+    id.0.checked_add(1)
+        .filter(|&next| next != OutputID::DISCONNECTED.node.0)
+        .map(ID)
+}
+
+/// Lets another thread follow and stop an evaluation
+#[derive(Debug, Default)]
+pub struct EvalControl {
+    cancelled: AtomicBool,
+    done: AtomicUsize,
+    total: AtomicUsize,
+}
+
+impl EvalControl {
+    /// Nodes that haven't started yet are skipped and fail with LazError::Cancelled. Running nodes
+    /// stop at their next check in LazNode::evaluate_with, or finish first if they don't check
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// How many nodes have finished, and how many have to be evaluated in total
+    pub fn progress(&self) -> (usize, usize) {
+        (self.done.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
+    }
+}
+
+/// How the last run of a node went
+#[derive(Clone, Debug)]
+pub struct EvalRecord {
+    /// When the node finished running
+    pub finished: SystemTime,
+    pub duration: Duration,
+    /// Without node context
+    pub error: Option<LazError>,
+    /// Total LazValue::size of the outputs, 0 if the node failed
+    pub output_size: usize,
+    /// How the node's own cache was used in this run
+    pub node_cache: CacheStats,
+}
+
+/// Totals over the runs of a node, for finding slow nodes and checking that caches work
+#[derive(Clone, Debug, Default)]
+pub struct NodeProfile {
+    pub runs: u64,
+    pub total_time: Duration,
+    pub max_time: Duration,
+    /// Outputs of the last successful run, in bytes
+    pub output_size: usize,
+    /// Times the outputs were needed and taken from the env's cache instead of running the node
+    pub cache_hits: u64,
+    pub node_cache: CacheStats,
+}
+
+impl NodeProfile {
+    pub fn mean_time(&self) -> Duration {
+        if self.runs == 0 { Duration::default() } else { self.total_time / self.runs as u32 }
+    }
+
+    /// How often the outputs were needed and already cached. None if they were never needed
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        CacheStats { hits: self.cache_hits, misses: self.runs }.hit_rate()
+    }
+
+    fn add_run(&mut self, record: &EvalRecord) {
+        self.runs += 1;
+        self.total_time += record.duration;
+        self.max_time = self.max_time.max(record.duration);
+        if record.error.is_none() {
+            self.output_size = record.output_size;
+        }
+        self.node_cache.hits += record.node_cache.hits;
+        self.node_cache.misses += record.node_cache.misses;
+    }
+
+    fn add(&mut self, other: &NodeProfile) {
+        self.runs += other.runs;
+        self.total_time += other.total_time;
+        self.max_time = self.max_time.max(other.max_time);
+        if other.runs > 0 {
+            self.output_size = other.output_size;
+        }
+        self.cache_hits += other.cache_hits;
+        self.node_cache.hits += other.node_cache.hits;
+        self.node_cache.misses += other.node_cache.misses;
+    }
+}
 
 #[derive(Default)]
 pub struct LazEnv {
@@ -10,26 +125,252 @@ pub struct LazEnv {
 
     smallest_unused_id: ID, // Invariant: !self.nodes.contains(self.smallest_unused_id)
 
+    // Outputs of the last evaluation of each node, by port
+    cache: HashMap<OutputID, LazValue>,
+    // How many outputs each node in the cache has. A node missing from here is dirty and has to be
+    // reevaluated
+    evaluated: HashMap<ID, usize>,
+
+    // The last run of each node. Kept when the node is invalidated
+    records: HashMap<ID, EvalRecord>,
+    profiles: HashMap<ID, NodeProfile>,
+
     selected: Option<ID>,
+
+    // The output that is shown on screen
+    displayed: Option<OutputID>,
+
+    // Counts changes that can affect evaluation results
+    generation: u64,
+}
+
+impl std::fmt::Debug for LazEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazEnv")
+            .field("n_nodes", &self.nodes.len())
+            .field("n_cached", &self.evaluated.len())
+            .field("selected", &self.selected)
+            .field("displayed", &self.displayed)
+            .finish()
+    }
 }
 
 impl LazEnv {
-    pub fn add_node(&mut self, node: Box<dyn LazNode>) -> ID {
-        let id = self.smallest_unused_id.clone();
+    /// Adds a node with the smallest ID that hasn't been used yet
+    pub fn add_node(&mut self, node: Box<dyn LazNode>) -> Result<ID, LazError> {
+        let id = self.smallest_unused_id;
+        self.insert_node(id, node)?;
+        Ok(id)
+    }
+
+    /// Adds a node with a given ID, for example when undoing its removal
+    pub fn insert_node(&mut self, id: ID, node: Box<dyn LazNode>) -> Result<(), LazError> {
+        let next_id = self.check_new_id(id)?;
+        self.generation += 1;
         self.nodes.insert(id, node);
-        self.smallest_unused_id.0 += 1;
+        self.smallest_unused_id.0 = self.smallest_unused_id.0.max(next_id.0);
+        Ok(())
+    }
+
+    /// Whether insert_node would accept `id`. Returns the ID after it
+    pub fn check_new_id(&self, id: ID) -> Result<ID, LazError> {
+        if self.nodes.contains_key(&id) {
+            return Err(LazError::NodeExists(id));
+        }
+        id_after(id).ok_or(LazError::InvalidID(id))
+    }
 
-        id
+    /// Nodes connected to the removed node are left pointing to it, and fail to evaluate until
+    /// they're reconnected or the node is added back
+    pub fn remove_node(&mut self, id: ID) -> Result<Box<dyn LazNode>, LazError> {
+        self.invalidate(id);
+        let node = self.nodes.remove(&id).ok_or(LazError::NoSuchNode(id))?;
+        self.records.remove(&id);
+        self.profiles.remove(&id);
+        if self.selected == Some(id) {
+            self.selected = None;
+        }
+        Ok(node)
+    }
+
+    /// Swaps out a node, keeping its ID. Returns the old node
+    pub fn replace_node(&mut self, id: ID, node: Box<dyn LazNode>) -> Result<Box<dyn LazNode>, LazError> {
+        if !self.nodes.contains_key(&id) {
+            return Err(LazError::NoSuchNode(id));
+        }
+        self.invalidate(id);
+        // Unwrap is fine, we checked that the node exists
+        Ok(self.nodes.insert(id, node).unwrap())
+    }
+
+    pub fn next_id(&self) -> ID {
+        self.smallest_unused_id
+    }
+
+    pub fn ids(&self) -> Vec<ID> {
+        let mut ids = self.nodes.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids
     }
 
     pub fn get_node(&self, id: ID) -> Option<&dyn LazNode> {
         self.nodes.get(&id).map(|x| x.as_ref())
     }
 
+    /// Marks the node as dirty, as we can't know what the caller changes
     pub fn get_node_mut(&mut self, id: ID) -> Option<&mut (dyn LazNode + 'static)> {
+        self.invalidate(id);
         self.nodes.get_mut(&id).map(|x| x.as_mut())
     }
 
+    /// Nodes that directly use any output of `id`
+    pub fn dependents(&self, id: ID) -> Vec<ID> {
+        self.nodes.iter()
+            .filter(|(_, node)| node.inputs().into_iter().any(|input| input.node == id))
+            .map(|(&dep_id, _)| dep_id)
+            .collect()
+    }
+
+    /// Marks a node and everything downstream of it as dirty
+    pub fn invalidate(&mut self, id: ID) {
+        self.generation += 1;
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            // A node can only be cached if all its inputs are, so we can stop at dirty nodes
+            if let Some(n_outputs) = self.evaluated.remove(&id) {
+                for outport in 0..n_outputs {
+                    self.cache.remove(&OutputID { node: id, outport });
+                }
+                stack.extend(self.dependents(id));
+            }
+        }
+    }
+
+    pub fn is_dirty(&self, id: ID) -> bool {
+        !self.evaluated.contains_key(&id)
+    }
+
+    /// How the node's last run went, if it has been run. Nodes that weren't run because an input
+    /// failed keep their previous record
+    pub fn last_evaluation(&self, id: ID) -> Option<&EvalRecord> {
+        self.records.get(&id)
+    }
+
+    pub fn profile(&self, id: ID) -> Option<&NodeProfile> {
+        self.profiles.get(&id)
+    }
+
+    /// Profiles of every node that has been needed, by total time spent running them, slowest
+    /// first
+    pub fn slowest_nodes(&self) -> Vec<(ID, &NodeProfile)> {
+        let mut profiles = self.profiles.iter().map(|(&id, profile)| (id, profile)).collect::<Vec<_>>();
+        profiles.sort_by(|a, b| b.1.total_time.cmp(&a.1.total_time).then(a.0.cmp(&b.0)));
+        profiles
+    }
+
+    /// How often a needed output was already cached, over all nodes
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let hits = self.profiles.values().map(|profile| profile.cache_hits).sum();
+        let misses = self.profiles.values().map(|profile| profile.runs).sum();
+        CacheStats { hits, misses }.hit_rate()
+    }
+
+    pub fn reset_profiles(&mut self) {
+        self.profiles.clear();
+    }
+
+    /// Changes whenever a node is added or invalidated, so results computed from a snapshot can be
+    /// checked for being outdated
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// A copy of the dirty part of the graph that `id` depends on, together with the cached
+    /// outputs it uses, that can be evaluated on another thread. Only the ports that are used are
+    /// copied, not every output of the nodes they belong to. Nodes are copied with
+    /// LazNode::duplicate, so state like the contents of a file is shared with the snapshot
+    pub fn snapshot(&self, id: ID, registry: &NodeRegistry) -> Result<LazEnv, LazError> {
+        let mut snapshot = LazEnv::default();
+
+        for id in self.dirty_dependencies(id) {
+            let node = &self.nodes[&id];
+            let copy = node.duplicate(registry)?;
+
+            for input in node.inputs() {
+                if let Some(&n_outputs) = self.evaluated.get(&input.node) {
+                    snapshot.evaluated.insert(input.node, n_outputs);
+                    if let Some(value) = self.cache.get(input) {
+                        snapshot.cache.insert(*input, value.clone());
+                    }
+                }
+            }
+            snapshot.nodes.insert(id, copy);
+        }
+        snapshot.smallest_unused_id = self.smallest_unused_id;
+
+        Ok(snapshot)
+    }
+
+    /// A copy of the whole graph and its cache, with nodes copied by LazNode::duplicate
+    pub fn duplicate(&self, registry: &NodeRegistry) -> Result<LazEnv, LazError> {
+        let mut copy = LazEnv::default();
+        for (&id, node) in &self.nodes {
+            copy.nodes.insert(id, node.duplicate(registry)?);
+        }
+        copy.cache = self.cache.clone();
+        copy.evaluated = self.evaluated.clone();
+        copy.smallest_unused_id = self.smallest_unused_id;
+        copy.displayed = self.displayed;
+        Ok(copy)
+    }
+
+    /// Takes the cached outputs of an evaluated snapshot, and its nodes so state they keep between
+    /// evaluations (like the contents of a file) isn't lost. Only valid if the generation hasn't
+    /// changed since the snapshot was taken
+    pub fn merge_snapshot(&mut self, snapshot: LazEnv) {
+        // Nodes that were cached before the snapshot was taken only have the used ports in it
+        for (id, n_outputs) in snapshot.evaluated {
+            if self.nodes.contains_key(&id) && self.is_dirty(id) {
+                self.evaluated.insert(id, n_outputs);
+                for outport in 0..n_outputs {
+                    let output = OutputID { node: id, outport };
+                    if let Some(value) = snapshot.cache.get(&output) {
+                        self.cache.insert(output, value.clone());
+                    }
+                }
+            }
+        }
+        for (id, node) in snapshot.nodes {
+            if let Some(old) = self.nodes.get_mut(&id) {
+                *old = node;
+            }
+        }
+        for (id, record) in snapshot.records {
+            if self.nodes.contains_key(&id) {
+                self.records.insert(id, record);
+            }
+        }
+        for (id, profile) in snapshot.profiles {
+            if self.nodes.contains_key(&id) {
+                self.profiles.entry(id).or_default().add(&profile);
+            }
+        }
+    }
+
+    /// Asks every node whether something outside the graph (like a file) has changed, and
+    /// invalidates the ones that have. Returns whether any had
+    pub fn refresh(&mut self) -> bool {
+        let changed = self.nodes.iter_mut()
+            .filter_map(|(&id, node)| if node.has_external_changes() { Some(id) } else { None })
+            .collect::<Vec<_>>();
+
+        let any_changed = !changed.is_empty();
+        for id in changed {
+            self.invalidate(id);
+        }
+        any_changed
+    }
+
     fn inputs_for(&self, id: ID) -> Result<Vec<OutputID>, LazError> {
         Ok(
             self.nodes.get(&id).ok_or(LazError::NoSuchNode(id))?.inputs()
@@ -37,21 +378,704 @@ impl LazEnv {
         )
     }
 
+    pub fn output_type(&self, output: OutputID) -> Result<LazType, LazError> {
+        let node = self.nodes.get(&output.node).ok_or(LazError::NoSuchNode(output.node))?;
+        node.io_description().outputs.into_iter().nth(output.outport)
+            .map(|port| port.ty)
+            .ok_or(LazError::NoSuchOutport(output))
+    }
+
+    pub fn input_type(&self, input: InputID) -> Result<LazType, LazError> {
+        let node = self.nodes.get(&input.node).ok_or(LazError::NoSuchNode(input.node))?;
+        node.io_description().inputs.into_iter().nth(input.inport)
+            .map(|port| port.ty)
+            .ok_or(LazError::NoSuchInport(input))
+    }
+
+    /// Checks that a value from `from` can be fed into `to`, without evaluating anything
+    pub fn check_connection(&self, from: OutputID, to: InputID) -> Result<(), LazError> {
+        let actual = self.output_type(from)?;
+        let expected = self.input_type(to)?;
+        if expected.accepts(&actual) {
+            Ok(())
+        } else {
+            Err(LazError::InvalidInputType { from, expected, actual })
+        }
+    }
+
+    pub fn connect(&mut self, from: OutputID, to: InputID) -> Result<(), LazError> {
+        self.check_connection(from, to)?;
+        self.set_input(from, to)?;
+        Ok(())
+    }
+
+    /// Connects without type checking. Returns what the input was connected to before
+    pub fn set_input(&mut self, from: OutputID, to: InputID) -> Result<OutputID, LazError> {
+        let node = self.nodes.get_mut(&to.node).ok_or(LazError::NoSuchNode(to.node))?;
+        let input = node.inputs_muts().into_iter().nth(to.inport).ok_or(LazError::NoSuchInport(to))?;
+        let old = std::mem::replace(input, from);
+
+        self.invalidate(to.node);
+        Ok(old)
+    }
+
+    /// Serializes the graph to RON. Caches and the selection aren't saved
+    pub fn save(&self) -> Result<String, LazError> {
+        ron::ser::to_string_pretty(&self.saved(), ron::ser::PrettyConfig::default())
+            .map_err(|e| LazError::Format(e.to_string()))
+    }
+
+    pub(crate) fn saved(&self) -> SavedEnv {
+        let nodes = self.ids().into_iter()
+            .map(|id| {
+                let node = &self.nodes[&id];
+                SavedNode {
+                    id,
+                    kind: node.kind().into(),
+                    params: node.params(),
+                    inputs: node.inputs().into_iter().cloned().collect(),
+                }
+            })
+            .collect();
+
+        SavedEnv { nodes, displayed: self.displayed }
+    }
+
+    /// Rebuilds a graph saved with LazEnv::save, looking up node kinds in `registry`
+    pub fn load(text: &str, registry: &NodeRegistry) -> Result<LazEnv, LazError> {
+        let saved = ron::de::from_str(text).map_err(|e| LazError::Format(e.to_string()))?;
+        LazEnv::from_saved(saved, registry)
+    }
+
+    pub(crate) fn from_saved(saved: SavedEnv, registry: &NodeRegistry) -> Result<LazEnv, LazError> {
+        let mut env = LazEnv::default();
+        for saved_node in saved.nodes {
+            if env.nodes.contains_key(&saved_node.id) {
+                return Err(LazError::Format(format!("Duplicate node {}", saved_node.id)));
+            }
+            let next_id = id_after(saved_node.id)
+                .ok_or_else(|| LazError::Format(format!("Invalid node ID {}", saved_node.id)))?;
+
+            let mut node = registry.load(&saved_node.kind, &saved_node.params)?;
+            let inputs = node.inputs_muts();
+            check_arity(&saved_node.inputs, inputs.len())?;
+            for (input, saved_input) in inputs.into_iter().zip(saved_node.inputs) {
+                *input = saved_input;
+            }
+
+            env.nodes.insert(saved_node.id, node);
+            env.smallest_unused_id.0 = env.smallest_unused_id.0.max(next_id.0);
+        }
+        env.displayed = saved.displayed;
+
+        Ok(env)
+    }
+
+    pub fn set_selected(&mut self, id: Option<ID>) {
+        self.selected = id;
+    }
+
+    pub fn selected(&self) -> Option<ID> {
+        self.selected
+    }
+
+    pub fn set_displayed(&mut self, output: Option<OutputID>) {
+        self.displayed = output;
+    }
+
+    pub fn displayed(&self) -> Option<OutputID> {
+        self.displayed
+    }
+
+    /// Evaluates a node, only recomputing the parts of the graph that are dirty. Errors are wrapped
+    /// in LazError::InNode for each node being evaluated
+    ///
+    /// Independent nodes are evaluated in parallel. The result, including which error is returned
+    /// if several nodes fail, is the same as evaluating the inputs of each node one by one in order
     pub fn evaluate_node(&mut self, id: ID) -> Result<Vec<LazValue>, LazError> {
-        let input_refs = self.nodes.get(&id).ok_or(LazError::NoSuchNode(id))?.inputs();
-        // We need to clone each element because the recursive evaluate_node call might modify this
-        // node's input refs
-        let input_ids = input_refs.into_iter().cloned().collect::<Vec<_>>();
+        self.evaluate_node_with(id, &EvalControl::default())
+    }
+
+    /// Like evaluate_node, reporting progress to and checking for cancellation in `control`
+    pub fn evaluate_node_with(&mut self, id: ID, control: &EvalControl) -> Result<Vec<LazValue>, LazError> {
+        let failed = self.evaluate_parallel(id, control);
+        self.find_result(id, &failed, &mut HashSet::new())?;
+        // Indexing is fine, find_result checked that the node is cached
+        let n_outputs = self.evaluated[&id];
+        Ok((0..n_outputs).filter_map(|outport| self.cache.get(&OutputID { node: id, outport }).cloned()).collect())
+    }
+
+    /// Like evaluate_node_with, but only returns the value of one port, so the other outputs aren't
+    /// copied. They are still cached, so reading them later doesn't evaluate the node again
+    pub fn evaluate_output_with(&mut self, output: OutputID, control: &EvalControl) -> Result<LazValue, LazError> {
+        let failed = self.evaluate_parallel(output.node, control);
+        self.find_result(output.node, &failed, &mut HashSet::new())?;
+        self.cache.get(&output).cloned().ok_or(LazError::NoSuchOutport(output))
+    }
+
+    // Dirty nodes that have to be evaluated to evaluate `id`, including `id` itself
+    fn dirty_dependencies(&self, id: ID) -> HashSet<ID> {
+        let mut found = HashSet::new();
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if !self.is_dirty(id) || found.contains(&id) {
+                continue;
+            }
+            if let Some(node) = self.nodes.get(&id) {
+                found.insert(id);
+                stack.extend(node.inputs().into_iter().map(|input| input.node));
+            }
+        }
+        found
+    }
+
+    // Evaluates everything that can be evaluated of the dirty part of the graph `id` depends on.
+    // Nodes downstream of a failed node, or in a cycle, are left dirty. Returns the errors of the
+    // nodes that failed, without node context
+    fn evaluate_parallel(&mut self, id: ID, control: &EvalControl) -> HashMap<ID, LazError> {
+        let needed = self.dirty_dependencies(id);
+        control.total.fetch_add(needed.len(), Ordering::Relaxed);
+
+        // Each use of a cached output, by the caller or a node being evaluated, is a cache hit
+        let used_cached = needed.iter()
+            .flat_map(|id| self.nodes[id].inputs().into_iter().map(|input| input.node).collect::<HashSet<_>>())
+            .chain(std::iter::once(id))
+            .filter(|input| !self.is_dirty(*input))
+            .collect::<Vec<_>>();
+        for input in used_cached {
+            self.profiles.entry(input).or_default().cache_hits += 1;
+        }
+
+        let mut waiting_for = HashMap::new();
+        let mut dependents: HashMap<ID, Vec<ID>> = HashMap::new();
+        for &id in &needed {
+            let inputs = self.nodes[&id].inputs().into_iter()
+                .map(|input| input.node)
+                .filter(|input| needed.contains(input))
+                .collect::<HashSet<_>>();
+            for &input in &inputs {
+                dependents.entry(input).or_default().push(id);
+            }
+            waiting_for.insert(id, inputs);
+        }
+
+        // The nodes are moved out of the env while evaluating, so they can be sent to other threads
+        let nodes = needed.iter()
+            .map(|id| (*id, self.nodes.remove(id).unwrap()))
+            .collect();
+
+        let ready = waiting_for.iter()
+            .filter(|(_, inputs)| inputs.is_empty())
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+
+        let schedule = Mutex::new(Schedule {
+            nodes,
+            cache: std::mem::take(&mut self.cache),
+            evaluated: std::mem::take(&mut self.evaluated),
+            failed: HashMap::new(),
+            records: HashMap::new(),
+            waiting_for,
+            dependents,
+        });
 
-        let mut values = Vec::with_capacity(input_ids.len());
+        rayon::scope(|scope| {
+            for id in ready {
+                spawn_node(scope, &schedule, control, id);
+            }
+        });
+
+        // A panicking node would already have propagated out of rayon::scope
+        let schedule = schedule.into_inner().unwrap();
+        self.nodes.extend(schedule.nodes);
+        self.cache = schedule.cache;
+        self.evaluated = schedule.evaluated;
+        for (id, record) in schedule.records {
+            self.profiles.entry(id).or_default().add_run(&record);
+            self.records.insert(id, record);
+        }
+        schedule.failed
+    }
+
+    // Walks the graph like a serial evaluator would, evaluating inputs in order. Everything that
+    // could be evaluated is cached at this point, so this only finds the first error
+    fn find_result(&self, id: ID, failed: &HashMap<ID, LazError>, visiting: &mut HashSet<ID>) -> Result<(), LazError> {
+        if !self.is_dirty(id) {
+            return Ok(());
+        }
+        if !self.nodes.contains_key(&id) {
+            return Err(LazError::NoSuchNode(id));
+        }
+
+        self.find_error(id, failed, visiting)
+            .map_err(|e| LazError::InNode { node: id, source: Box::new(e) })
+    }
+
+    fn find_error(&self, id: ID, failed: &HashMap<ID, LazError>, visiting: &mut HashSet<ID>) -> Result<(), LazError> {
+        if !visiting.insert(id) {
+            return Err(LazError::Cycle(id));
+        }
+
+        let node = self.nodes.get(&id).ok_or(LazError::NoSuchNode(id))?;
+        let input_ids = node.inputs().into_iter().cloned().collect::<Vec<_>>();
+        check_arity(&input_ids, node.io_description().inputs.len())?;
 
         for input_id in input_ids {
-            let outputs: Vec<LazValue> = self.evaluate_node(input_id.node)?;
-            let value = outputs.into_iter().nth(input_id.outport).ok_or(LazError::NoSuchOutport(input_id))?;
-            values.push((input_id, value));
+            self.find_result(input_id.node, failed, visiting)?;
+            if !self.cache.contains_key(&input_id) {
+                return Err(LazError::NoSuchOutport(input_id));
+            }
+        }
+
+        visiting.remove(&id);
+        // All inputs were evaluated, so the node must have been run and failed
+        Err(failed.get(&id).cloned().unwrap_or(LazError::Cycle(id)))
+    }
+}
+
+// Shared between the threads evaluating nodes
+struct Schedule {
+    // Nodes that haven't been evaluated yet, or have finished
+    nodes: HashMap<ID, Box<dyn LazNode>>,
+    cache: HashMap<OutputID, LazValue>,
+    evaluated: HashMap<ID, usize>,
+    failed: HashMap<ID, LazError>,
+    records: HashMap<ID, EvalRecord>,
+    // The dirty inputs each node is waiting on
+    waiting_for: HashMap<ID, HashSet<ID>>,
+    dependents: HashMap<ID, Vec<ID>>,
+}
+
+// Evaluates a node whose inputs are all cached, then starts the nodes that were waiting only for it
+fn spawn_node<'s>(scope: &rayon::Scope<'s>, schedule: &'s Mutex<Schedule>, control: &'s EvalControl, id: ID) {
+    scope.spawn(move |scope| {
+        if control.is_cancelled() {
+            schedule.lock().unwrap().failed.insert(id, LazError::Cancelled);
+            return;
+        }
+
+        let (mut node, inputs) = {
+            let mut state = schedule.lock().unwrap();
+            // Unwrap is fine, each node is only spawned once
+            let node = state.nodes.remove(&id).unwrap();
+            let inputs = cached_inputs(node.as_ref(), &state.cache, &state.evaluated);
+            (node, inputs)
+        };
+
+        let node_cache = node.cache_stats();
+        let start = Instant::now();
+        // Read here rather than while holding the lock, and timed as part of the node
+        let inputs = if node.streams_inputs() { inputs } else { inputs.and_then(materialize_inputs) };
+        let result = inputs
+            .and_then(|inputs| node.evaluate_with(inputs, control))
+            .and_then(|outputs| check_outputs(node.as_ref(), outputs));
+        let duration = start.elapsed();
+        let node_cache_after = node.cache_stats();
+        let record = EvalRecord {
+            finished: SystemTime::now(),
+            duration,
+            error: result.as_ref().err().cloned(),
+            output_size: result.as_ref().map(|outputs| outputs.iter().map(|x| x.size()).sum()).unwrap_or(0),
+            node_cache: CacheStats {
+                hits: node_cache_after.hits - node_cache.hits,
+                misses: node_cache_after.misses - node_cache.misses,
+            },
+        };
+        control.done.fetch_add(1, Ordering::Relaxed);
+
+        let mut state = schedule.lock().unwrap();
+        state.nodes.insert(id, node);
+        state.records.insert(id, record);
+
+        let ready = match result {
+            Ok(outputs) => {
+                state.evaluated.insert(id, outputs.len());
+                for (outport, value) in outputs.into_iter().enumerate() {
+                    state.cache.insert(OutputID { node: id, outport }, value);
+                }
+
+                let mut ready = Vec::new();
+                for dependent in state.dependents.remove(&id).unwrap_or_default() {
+                    let waiting = state.waiting_for.get_mut(&dependent).unwrap();
+                    waiting.remove(&id);
+                    if waiting.is_empty() {
+                        ready.push(dependent);
+                    }
+                }
+                ready
+            }
+            Err(e) => {
+                state.failed.insert(id, e);
+                Vec::new()
+            }
+        };
+        drop(state);
+
+        for id in ready {
+            spawn_node(scope, schedule, control, id);
         }
+    });
+}
+
+fn cached_inputs(node: &dyn LazNode, cache: &HashMap<OutputID, LazValue>, evaluated: &HashMap<ID, usize>) -> Result<Vec<(OutputID, LazValue)>, LazError> {
+    let input_ids = node.inputs();
+    check_arity(&input_ids, node.io_description().inputs.len())?;
+
+    input_ids.into_iter()
+        .map(|&input_id| {
+            if !evaluated.contains_key(&input_id.node) {
+                return Err(LazError::NoSuchNode(input_id.node));
+            }
+            let value = cache.get(&input_id).ok_or(LazError::NoSuchOutport(input_id))?;
+            Ok((input_id, value.clone()))
+        })
+        .collect()
+}
+
+// Nodes have to return a value for each output port, so each port can be cached and read separately
+fn check_outputs(node: &dyn LazNode, outputs: Vec<LazValue>) -> Result<Vec<LazValue>, LazError> {
+    let expected = node.io_description().outputs.len();
+    if outputs.len() != expected {
+        return Err(LazError::OutputCountMismatch { expected, actual: outputs.len() });
+    }
+    Ok(outputs)
+}
+
+fn materialize_inputs(inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<(OutputID, LazValue)>, LazError> {
+    inputs.into_iter()
+        .map(|(input_id, value)| Ok((input_id, value.materialize()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::{ConstantNode, ReadFileNode};
+    use crate::laz::byte_nodes::{ConcatNode, ReverseNode, SliceNode, SplitNode, StrideNode, XorNode};
+    use crate::laz::types::Matrix;
+
+    fn bytes(data: &[u8]) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Bytes(data.to_vec().into()) })
+    }
+
+    fn output(node: ID) -> OutputID {
+        OutputID { node, outport: 0 }
+    }
+
+    fn runs(env: &LazEnv, id: ID) -> u64 {
+        env.profile(id).map(|profile| profile.runs).unwrap_or(0)
+    }
+
+    #[test]
+    fn editing_a_constant_only_reruns_its_descendants() {
+        let mut env = LazEnv::default();
+        let first = env.add_node(bytes(&[1, 2])).unwrap();
+        let second = env.add_node(bytes(&[3])).unwrap();
+        let concat = env.add_node(Box::new(ConcatNode { first: output(first), second: output(second) })).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(second) })).unwrap();
+
+        env.evaluate_node(concat).unwrap();
+        env.evaluate_node(reverse).unwrap();
+        for &id in &[first, second, concat, reverse] {
+            assert_eq!(runs(&env, id), 1);
+        }
+
+        env.replace_node(first, bytes(&[4])).unwrap();
+        assert!(env.is_dirty(first) && env.is_dirty(concat));
+        assert!(!env.is_dirty(second) && !env.is_dirty(reverse));
+
+        let concatenated = env.evaluate_node(concat).unwrap();
+        assert_eq!(&*concatenated[0].as_bytes().unwrap(), &[4, 3]);
+        env.evaluate_node(reverse).unwrap();
+        assert_eq!(runs(&env, first), 2);
+        assert_eq!(runs(&env, concat), 2);
+        assert_eq!(runs(&env, second), 1);
+        assert_eq!(runs(&env, reverse), 1);
+    }
+
+    #[test]
+    fn both_split_outputs_share_one_run() {
+        let mut env = LazEnv::default();
+        let source = env.add_node(bytes(&[1, 2, 3, 4])).unwrap();
+        let offset = env.add_node(Box::new(ConstantNode { value: LazValue::Unsigned(1) })).unwrap();
+        let split = env.add_node(Box::new(SplitNode { input_bytes: output(source), offset: output(offset) })).unwrap();
+        let head = env.add_node(Box::new(ReverseNode { input_bytes: OutputID { node: split, outport: 0 } })).unwrap();
+        let tail = env.add_node(Box::new(ReverseNode { input_bytes: OutputID { node: split, outport: 1 } })).unwrap();
+
+        assert_eq!(&*env.evaluate_node(head).unwrap()[0].as_bytes().unwrap(), &[1]);
+        assert_eq!(&*env.evaluate_node(tail).unwrap()[0].as_bytes().unwrap(), &[4, 3, 2]);
+        assert_eq!(runs(&env, split), 1);
+        assert_eq!(runs(&env, head), 1);
+        assert_eq!(runs(&env, tail), 1);
+    }
+
+    #[test]
+    fn errors_name_the_nodes_leading_to_the_failure() {
+        let path = std::env::temp_dir().join(format!("laz-{}-missing", std::process::id()));
+        let mut env = LazEnv::default();
+        let name = env.add_node(Box::new(ConstantNode { value: LazValue::String(path.to_string_lossy().into_owned()) })).unwrap();
+        let read = env.add_node(Box::new(ReadFileNode::new(output(name)))).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(read) })).unwrap();
+
+        let err = env.evaluate_node(reverse).unwrap_err();
+        assert_eq!(err.node_chain(), vec![reverse, read]);
+        assert_eq!(err.failing_node(), Some(read));
+        assert!(matches!(err.root_cause(), LazError::Io { .. }));
+        assert_eq!(err.to_string(), format!("Error evaluating node {}", reverse));
+
+        // The sources lead to the IO error
+        let mut cause: &dyn std::error::Error = &err;
+        while let Some(source) = cause.source() {
+            cause = source;
+        }
+        assert!(cause.downcast_ref::<std::io::Error>().is_some());
+    }
+
+    #[test]
+    fn save_and_load_every_node_kind() {
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let source = env.add_node(bytes(&[1, 2])).unwrap();
+        for node_type in registry.types() {
+            let mut node = node_type.create(&registry).unwrap();
+            for input in node.inputs_muts() {
+                *input = output(source);
+            }
+            env.add_node(node).unwrap();
+        }
+        // Constants save their value as parameters
+        let values = vec![
+            LazValue::Matrix(Matrix::new(2, 1, vec![0.5, -1.]).unwrap()),
+            LazValue::Record(vec![ ("x".into(), LazValue::Signed(-3)), ("y".into(), LazValue::Char('z')) ]),
+            LazValue::Tuple(vec![ LazValue::Float(1.25), LazValue::String("text".into()) ]),
+            LazValue::UnsignedArray(vec![1, u64::MAX].into()),
+        ];
+        for value in values {
+            env.add_node(Box::new(ConstantNode { value })).unwrap();
+        }
+        env.set_displayed(Some(output(source)));
+
+        let text = env.save().unwrap();
+        let loaded = LazEnv::load(&text, &registry).unwrap();
+        assert_eq!(loaded.ids(), env.ids());
+        for id in env.ids() {
+            let (node, loaded_node) = (env.get_node(id).unwrap(), loaded.get_node(id).unwrap());
+            assert_eq!(node.kind(), loaded_node.kind());
+            assert_eq!(node.inputs(), loaded_node.inputs());
+            assert_eq!(format!("{:?}", node.params()), format!("{:?}", loaded_node.params()));
+        }
+        assert_eq!(loaded.displayed(), env.displayed());
+        assert_eq!(loaded.next_id(), env.next_id());
+        assert_eq!(loaded.save().unwrap(), text);
+    }
+
+    #[test]
+    fn loading_a_matrix_with_the_wrong_size_fails() {
+        let mut env = LazEnv::default();
+        env.add_node(Box::new(ConstantNode { value: LazValue::Matrix(Matrix::new(2, 1, vec![0., 1.]).unwrap()) })).unwrap();
+        let text = env.save().unwrap();
+        assert!(text.contains("width: 2"));
+
+        let text = text.replace("width: 2", "width: 3");
+        let err = LazEnv::load(&text, &NodeRegistry::builtin()).unwrap_err();
+        assert!(matches!(err, LazError::Format(_)), "{}", err);
+    }
+
+    #[test]
+    fn loading_the_disconnected_id_fails() {
+        let registry = NodeRegistry::builtin();
+        let saved_with_id = |id| SavedEnv {
+            nodes: vec![ SavedNode { id, kind: "Constant".into(), params: LazValue::Byte(0), inputs: vec![] } ],
+            displayed: None,
+        };
+
+        for &id in &[OutputID::DISCONNECTED.node, ID(OutputID::DISCONNECTED.node.0 - 1)] {
+            let err = LazEnv::from_saved(saved_with_id(id), &registry).unwrap_err();
+            assert!(matches!(err, LazError::Format(_)), "{}", err);
+        }
+
+        let env = LazEnv::from_saved(saved_with_id(ID(5)), &registry).unwrap();
+        assert_eq!(env.next_id(), ID(6));
+    }
+
+    #[test]
+    fn adding_a_node_after_the_last_valid_id_fails() {
+        let mut env = LazEnv::default();
+        let generation = env.generation();
+        let id = env.add_node(bytes(&[1])).unwrap();
+        assert_eq!(env.next_id(), ID(id.0 + 1));
+        assert!(env.generation() > generation);
+
+        let last = ID(OutputID::DISCONNECTED.node.0 - 2);
+        env.insert_node(last, bytes(&[2])).unwrap();
+        assert!(matches!(env.add_node(bytes(&[3])), Err(LazError::InvalidID(_))));
+        assert_eq!(env.ids(), vec![id, last]);
+    }
+
+    // xorshift64, so the random graphs are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    // Constants followed by nodes connected to random earlier nodes. Wrong types, empty Xor keys
+    // and zero strides make some of the nodes fail
+    fn random_graph(rng: &mut Rng, n_constants: usize, n_nodes: usize) -> Vec<Box<dyn LazNode>> {
+        let mut nodes = Vec::<Box<dyn LazNode>>::new();
+        for _ in 0..n_constants {
+            let node = if rng.below(3) == 0 {
+                ConstantNode { value: LazValue::Unsigned(rng.below(3)) }
+            } else {
+                let len = rng.below(6);
+                ConstantNode { value: LazValue::Bytes((0..len).map(|_| rng.below(256) as u8).collect::<Vec<_>>().into()) }
+            };
+            nodes.push(Box::new(node));
+        }
+        for _ in 0..n_nodes {
+            let kind = rng.below(4);
+            let mut earlier = || output(ID(rng.below(nodes.len() as u64)));
+            let node: Box<dyn LazNode> = match kind {
+                0 => Box::new(ConcatNode { first: earlier(), second: earlier() }),
+                1 => Box::new(XorNode { input_bytes: earlier(), key: earlier() }),
+                2 => Box::new(StrideNode { input_bytes: earlier(), stride: earlier(), offset: earlier() }),
+                _ => Box::new(ReverseNode { input_bytes: earlier() }),
+            };
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    // Evaluates the inputs of each node one by one, in order, stopping at the first error
+    fn evaluate_serially(nodes: &mut [Box<dyn LazNode>], id: ID, results: &mut HashMap<ID, Result<Vec<LazValue>, LazError>>) -> Result<Vec<LazValue>, LazError> {
+        if let Some(result) = results.get(&id) {
+            return result.clone();
+        }
+
+        let mut inputs = Vec::new();
+        let mut result = Ok(());
+        for input in nodes[id.0 as usize].inputs().into_iter().cloned().collect::<Vec<_>>() {
+            match evaluate_serially(nodes, input.node, results) {
+                Ok(values) => inputs.push((input, values[input.outport].clone())),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        let result = result
+            .and_then(|()| nodes[id.0 as usize].evaluate_for(inputs))
+            .map_err(|e| LazError::InNode { node: id, source: Box::new(e) });
+
+        results.insert(id, result.clone());
+        result
+    }
+
+    fn describe(result: &Result<Vec<LazValue>, LazError>) -> String {
+        match result {
+            Ok(values) => format!("{:?}", values),
+            Err(e) => format!("{:?}: {}", e.node_chain(), e.root_cause()),
+        }
+    }
+
+    #[test]
+    fn parallel_evaluation_matches_serial() {
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+        for _ in 0..50 {
+            let seed = rng.below(u64::MAX);
+            let mut env = LazEnv::default();
+            for node in random_graph(&mut Rng(seed), 4, 20) {
+                env.add_node(node).unwrap();
+            }
+            let mut serial_nodes = random_graph(&mut Rng(seed), 4, 20);
+            let mut serial_results = HashMap::new();
+
+            // Evaluating in a random order, so some nodes are already cached
+            let mut ids = env.ids();
+            for i in (1..ids.len()).rev() {
+                ids.swap(i, rng.below(i as u64 + 1) as usize);
+            }
+            for id in ids {
+                let parallel = env.evaluate_node(id);
+                let serial = evaluate_serially(&mut serial_nodes, id, &mut serial_results);
+                assert_eq!(describe(&parallel), describe(&serial), "Evaluating {}", id);
+            }
+        }
+    }
+
+    #[test]
+    fn finding_an_error_through_many_diamonds_is_fast() {
+        // Each level depends on the previous one twice, so walking every path would take 2^64 steps
+        let mut env = LazEnv::default();
+        let data = env.add_node(bytes(&[1])).unwrap();
+        let empty_key = env.add_node(bytes(&[])).unwrap();
+        let root = env.add_node(Box::new(XorNode { input_bytes: output(data), key: output(empty_key) })).unwrap();
+        let mut last = root;
+        for _ in 0..64 {
+            last = env.add_node(Box::new(ConcatNode { first: output(last), second: output(last) })).unwrap();
+        }
+
+        let err = env.evaluate_node(last).unwrap_err();
+        assert_eq!(err.failing_node(), Some(root));
+        assert_eq!(err.node_chain().len(), 65);
+    }
+
+    #[test]
+    fn snapshots_share_the_file_cache() {
+        let path = std::env::temp_dir().join(format!("laz-{}-snapshot", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        let path_value = || LazValue::String(path.to_string_lossy().into_owned());
+
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let name = env.add_node(Box::new(ConstantNode { value: path_value() })).unwrap();
+        let read = env.add_node(Box::new(ReadFileNode::new(output(name)))).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(read) })).unwrap();
+
+        for _ in 0..2 {
+            // Setting the same file name again makes the file be read again
+            env.replace_node(name, Box::new(ConstantNode { value: path_value() })).unwrap();
+            let mut snapshot = env.snapshot(reverse, &registry).unwrap();
+            let reversed = snapshot.evaluate_node(reverse).unwrap();
+            assert_eq!(&*reversed[0].as_bytes().unwrap(), b"cba");
+            env.merge_snapshot(snapshot);
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let stats = env.get_node(read).unwrap().cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn unwatched_file_that_changed_is_read_again() {
+        let path = std::env::temp_dir().join(format!("laz-{}-unwatched", std::process::id()));
+        std::fs::write(&path, b"abcd").unwrap();
+
+        // Only the middle of the file is read, so its contents aren't kept
+        let mut env = LazEnv::default();
+        let name = env.add_node(Box::new(ConstantNode { value: LazValue::String(path.to_string_lossy().into_owned()) })).unwrap();
+        let read = env.add_node(Box::new(ReadFileNode::new(output(name)))).unwrap();
+        let offset = env.add_node(Box::new(ConstantNode { value: LazValue::Unsigned(1) })).unwrap();
+        let length = env.add_node(Box::new(ConstantNode { value: LazValue::Unsigned(2) })).unwrap();
+        let slice = env.add_node(Box::new(SliceNode { input_bytes: output(read), offset: output(offset), length: output(length) })).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(slice) })).unwrap();
+        assert_eq!(&*env.evaluate_node(reverse).unwrap()[0].as_bytes().unwrap(), b"cb");
+
+        // The read is cached, so the slice reads a file that changed under it
+        std::fs::write(&path, b"vwxyz").unwrap();
+        assert!(!env.refresh());
+        env.set_input(output(slice), InputID { node: reverse, inport: 0 }).unwrap();
+        assert!(env.evaluate_node(reverse).is_err());
 
-        let node = self.nodes.get_mut(&id).ok_or(LazError::NoSuchNode(id))?;
-        node.evaluate_for(values)
+        // The failed read makes the next refresh pick up the new file
+        assert!(env.refresh());
+        assert_eq!(&*env.evaluate_node(reverse).unwrap()[0].as_bytes().unwrap(), b"xw");
+        assert!(!env.refresh());
+        std::fs::remove_file(&path).unwrap();
     }
 }
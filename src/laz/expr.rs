@@ -0,0 +1,1135 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use crate::laz::types::{LazValue, LazType};
+use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, check_arity, NumKind, num_kind};
+
+// Longer operators first, so "<<" isn't lexed as two "<"
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "(", ")", "[", "]", ",", ":", "?", ".",
+];
+
+enum Token {
+    Literal(LazValue),
+    Ident(String),
+    Op(&'static str),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// How deeply expressions can nest, counting each operator, call and bracket. Deeper expressions
+// fail to parse, as parsing and evaluating them recurses and could overflow the stack
+const MAX_DEPTH: usize = 64;
+
+// Binary operators from lowest to highest precedence. All are left associative
+const PRECEDENCE: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<", BinaryOp::Lt), ("<=", BinaryOp::Le), (">", BinaryOp::Gt), (">=", BinaryOp::Ge)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem)],
+];
+
+impl BinaryOp {
+    fn symbol(self) -> &'static str {
+        PRECEDENCE.iter()
+            .flat_map(|level| level.iter())
+            .find(|(_, op)| *op == self)
+            .map(|(symbol, _)| *symbol)
+            .unwrap_or("?")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Len,
+    Map,
+    Filter,
+    Fold,
+    Range,
+    Bytes,
+    Byte,
+    Unsigned,
+    Int,
+    Float,
+    Min,
+    Max,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "len" => Some(Function::Len),
+            "map" => Some(Function::Map),
+            "filter" => Some(Function::Filter),
+            "fold" => Some(Function::Fold),
+            "range" => Some(Function::Range),
+            "bytes" => Some(Function::Bytes),
+            "byte" => Some(Function::Byte),
+            "unsigned" => Some(Function::Unsigned),
+            "int" => Some(Function::Int),
+            "float" => Some(Function::Float),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            _ => None,
+        }
+    }
+
+    // Smallest and largest number of arguments
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Function::Map | Function::Filter | Function::Min | Function::Max => (2, 2),
+            Function::Fold => (3, 3),
+            Function::Range => (1, 2),
+            _ => (1, 1),
+        }
+    }
+
+    // The number of parameters of the lambda expected as argument `index`, or None if the
+    // argument is a value
+    fn lambda_params(self, index: usize) -> Option<usize> {
+        match (self, index) {
+            (Function::Map, 1) | (Function::Filter, 1) => Some(1),
+            (Function::Fold, 2) => Some(2),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(LazValue),
+    Var(String),
+    List(Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    Field(Box<Expr>, String),
+    TupleField(Box<Expr>, usize),
+    Call(Function, Vec<Expr>),
+    /// Only appears as an argument to the functions that take one
+    Lambda(Vec<String>, Box<Expr>),
+}
+
+fn parse_error(source: &str, offset: usize, reason: impl Into<String>) -> LazError {
+    LazError::ParseFailed { position: source[..offset].chars().count(), reason: reason.into() }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Integers are unsigned unless they have a u8, u64 or i64 suffix. Decimals with a fractional part
+// are floats, unless `fraction` is false, as after the "." of a tuple field where `t.0.1` is two
+// fields
+fn lex_number(text: &str, fraction: bool) -> Result<(LazValue, usize), String> {
+    let (radix, start) = if text.starts_with("0x") {
+        (16, 2)
+    } else if text.starts_with("0b") {
+        (2, 2)
+    } else {
+        (10, 0)
+    };
+    let mut end = start + text[start..].find(|c: char| !c.is_digit(radix) && c != '_').unwrap_or(text.len() - start);
+    let digits = text[start..end].replace('_', "");
+    if digits.is_empty() {
+        return Err("Expected digits".into());
+    }
+
+    let rest = &text[end..];
+    if fraction && radix == 10 && rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        end += 1 + rest[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - 1);
+        let value = text[..end].replace('_', "").parse::<f64>().map_err(|e| e.to_string())?;
+        return Ok((LazValue::Float(value), end));
+    }
+
+    let value = u64::from_str_radix(&digits, radix).map_err(|e| format!("Invalid number: {}", e))?;
+    let suffix_len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+    let value = match &rest[..suffix_len] {
+        "" | "u64" => LazValue::Unsigned(value),
+        "u8" => LazValue::Byte(u8::try_from(value).map_err(|_| format!("{} doesn't fit in u8", value))?),
+        "i64" => LazValue::Signed(i64::try_from(value).map_err(|_| format!("{} doesn't fit in i64", value))?),
+        suffix => return Err(format!("Unknown number suffix {:?}", suffix)),
+    };
+    Ok((value, end + suffix_len))
+}
+
+// The string literal at the start of `text`, and its length including quotes
+fn lex_string(text: &str) -> Result<(String, usize), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, i + 1)),
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some((_, 'n')) => '\n',
+                    Some((_, 't')) => '\t',
+                    Some((_, 'r')) => '\r',
+                    Some((_, '0')) => '\0',
+                    Some((_, '\\')) => '\\',
+                    Some((_, '"')) => '"',
+                    Some((_, c)) => return Err(format!("Unknown escape \\{}", c)),
+                    None => break,
+                };
+                value.push(escaped);
+            }
+            c => value.push(c),
+        }
+    }
+    Err("Unterminated string".into())
+}
+
+// Tokens with their byte offsets in source
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, LazError> {
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    while let Some(c) = source[offset..].chars().next() {
+        let rest = &source[offset..];
+        let (token, len) = if c.is_whitespace() {
+            offset += c.len_utf8();
+            continue;
+        } else if c.is_ascii_digit() {
+            let fraction = !matches!(tokens.last(), Some((_, Token::Op("."))));
+            let (value, len) = lex_number(rest, fraction).map_err(|reason| parse_error(source, offset, reason))?;
+            (Token::Literal(value), len)
+        } else if c == '"' {
+            let (value, len) = lex_string(rest).map_err(|reason| parse_error(source, offset, reason))?;
+            (Token::Literal(LazValue::String(value)), len)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let token = match &rest[..len] {
+                "true" => Token::Literal(LazValue::Bool(true)),
+                "false" => Token::Literal(LazValue::Bool(false)),
+                name => Token::Ident(name.into()),
+            };
+            (token, len)
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            (Token::Op(op), op.len())
+        } else {
+            return Err(parse_error(source, offset, format!("Unexpected character {:?}", c)));
+        };
+        tokens.push((offset, token));
+        offset += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    // Names that can be referred to: the inputs and the parameters of enclosing lambdas
+    scope: Vec<String>,
+    // Nesting of the expression being parsed, see MAX_DEPTH
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, reason: impl Into<String>) -> LazError {
+        let offset = self.tokens.get(self.next).map(|(offset, _)| *offset).unwrap_or_else(|| self.source.len());
+        parse_error(self.source, offset, reason)
+    }
+
+    fn peek_op(&self, op: &str) -> bool {
+        matches!(self.tokens.get(self.next), Some((_, Token::Op(next))) if *next == op)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        let found = self.peek_op(op);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), LazError> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(self.error(format!("Expected {:?}", op)))
+        }
+    }
+
+    // Goes one level deeper into the expression
+    fn enter(&mut self) -> Result<(), LazError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("Expression is nested too deeply"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Parses something nested in the current expression
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Expr, LazError>) -> Result<Expr, LazError> {
+        let depth = self.depth;
+        self.enter()?;
+        let expr = parse(self)?;
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn ident(&mut self) -> Result<String, LazError> {
+        match self.tokens.get(self.next) {
+            Some((_, Token::Ident(name))) => {
+                let name = name.clone();
+                self.next += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("Expected a name")),
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expr, LazError> {
+        self.nested(Parser::conditional)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, LazError> {
+        let condition = self.binary(0)?;
+        if !self.eat_op("?") {
+            return Ok(condition);
+        }
+        let then = self.expression()?;
+        self.expect_op(":")?;
+        let otherwise = self.expression()?;
+        Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, LazError> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        // Each operator nests the ones before it, as they're left associative
+        let depth = self.depth;
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for &(symbol, op) in PRECEDENCE[level] {
+                if self.eat_op(symbol) {
+                    self.enter()?;
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(op, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            self.depth = depth;
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, LazError> {
+        let op = if self.eat_op("-") {
+            UnaryOp::Neg
+        } else if self.eat_op("!") {
+            UnaryOp::Not
+        } else if self.eat_op("~") {
+            UnaryOp::BitNot
+        } else {
+            return self.postfix();
+        };
+        Ok(Expr::Unary(op, Box::new(self.nested(Parser::unary)?)))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, LazError> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        loop {
+            if self.peek_op("[") || self.peek_op(".") {
+                self.enter()?;
+            }
+            if self.eat_op("[") {
+                let start = if self.peek_op(":") { None } else { Some(Box::new(self.expression()?)) };
+                if self.eat_op(":") {
+                    let end = if self.peek_op("]") { None } else { Some(Box::new(self.expression()?)) };
+                    expr = Expr::Slice(Box::new(expr), start, end);
+                } else {
+                    // Unwrap is fine, start is only None if the next token is ":"
+                    expr = Expr::Index(Box::new(expr), start.unwrap());
+                }
+                self.expect_op("]")?;
+            } else if self.eat_op(".") {
+                expr = match self.tokens.get(self.next) {
+                    Some((_, Token::Ident(name))) => Expr::Field(Box::new(expr), name.clone()),
+                    Some((_, Token::Literal(LazValue::Unsigned(index)))) => Expr::TupleField(Box::new(expr), *index as usize),
+                    _ => return Err(self.error("Expected a field name or tuple index")),
+                };
+                self.next += 1;
+            } else {
+                self.depth = depth;
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, LazError> {
+        if self.eat_op("(") {
+            let expr = self.expression()?;
+            self.expect_op(")")?;
+            return Ok(expr);
+        }
+        if self.eat_op("[") {
+            let items = self.list("]", |parser, _| parser.expression())?;
+            return Ok(Expr::List(items));
+        }
+        if self.peek_op("|") {
+            return Err(self.error("Lambdas can only be passed to map, filter and fold"));
+        }
+
+        let expr = match self.tokens.get(self.next) {
+            Some((_, Token::Literal(value))) => Expr::Literal(value.clone()),
+            Some((_, Token::Ident(name))) => Expr::Var(name.clone()),
+            Some((_, Token::Op(op))) => return Err(self.error(format!("Unexpected {:?}", op))),
+            None => return Err(self.error("Unexpected end of expression")),
+        };
+        self.next += 1;
+
+        match expr {
+            Expr::Var(name) if self.peek_op("(") => self.call(&name),
+            Expr::Var(name) if !self.scope.contains(&name) => {
+                self.next -= 1;
+                Err(self.error(format!("Unknown name {:?}", name)))
+            }
+            expr => Ok(expr),
+        }
+    }
+
+    fn call(&mut self, name: &str) -> Result<Expr, LazError> {
+        let function = Function::from_name(name).ok_or_else(|| self.error(format!("Unknown function {:?}", name)))?;
+        self.expect_op("(")?;
+        let args = self.list(")", |parser, index| match function.lambda_params(index) {
+            Some(params) => parser.lambda(params),
+            None => parser.expression(),
+        })?;
+
+        let (min, max) = function.arity();
+        if args.len() < min || args.len() > max {
+            let expected = if min == max { min.to_string() } else { format!("{} to {}", min, max) };
+            return Err(self.error(format!("{} takes {} arguments, got {}", name, expected, args.len())));
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    fn lambda(&mut self, params: usize) -> Result<Expr, LazError> {
+        self.expect_op("|")?;
+        let names = self.list("|", |parser, _| parser.ident())?;
+        if names.len() != params {
+            return Err(self.error(format!("Expected {} lambda parameters, got {}", params, names.len())));
+        }
+        self.scope.extend(names.iter().cloned());
+        let body = self.expression();
+        self.scope.truncate(self.scope.len() - names.len());
+        Ok(Expr::Lambda(names, Box::new(body?)))
+    }
+
+    // Comma separated items up to and including `end`, which has already been opened
+    fn list<T>(&mut self, end: &str, mut item: impl FnMut(&mut Self, usize) -> Result<T, LazError>) -> Result<Vec<T>, LazError> {
+        let mut items = Vec::new();
+        if self.eat_op(end) {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self, items.len())?);
+            if self.eat_op(end) {
+                return Ok(items);
+            }
+            self.expect_op(",")?;
+        }
+    }
+}
+
+/// Parses `source`, which can refer to the names in `scope`
+fn parse(source: &str, scope: &[String]) -> Result<Expr, LazError> {
+    let mut parser = Parser {
+        source,
+        tokens: tokenize(source)?,
+        next: 0,
+        scope: scope.to_vec(),
+        depth: 0,
+    };
+    let expr = parser.expression()?;
+    if parser.next < parser.tokens.len() {
+        return Err(parser.error("Expected end of expression"));
+    }
+    Ok(expr)
+}
+
+fn fail(reason: impl Into<String>) -> LazError {
+    LazError::ExpressionFailed(reason.into())
+}
+
+// Integers of any kind, exactly
+fn to_i128(x: &LazValue) -> Option<i128> {
+    match *x {
+        LazValue::Byte(x) => Some(x as i128),
+        LazValue::Unsigned(x) => Some(x as i128),
+        LazValue::Signed(x) => Some(x as i128),
+        _ => None,
+    }
+}
+
+// Fits an integer into `kind`, failing if it's out of range
+fn fit(x: i128, kind: NumKind) -> Result<LazValue, LazError> {
+    let value = match kind {
+        NumKind::Byte => u8::try_from(x).ok().map(LazValue::Byte),
+        NumKind::Unsigned => u64::try_from(x).ok().map(LazValue::Unsigned),
+        NumKind::Signed => i64::try_from(x).ok().map(LazValue::Signed),
+        NumKind::Float => Some(LazValue::Float(x as f64)),
+    };
+    value.ok_or_else(|| fail(format!("{} doesn't fit in {:?}", x, kind)))
+}
+
+fn bits(kind: NumKind) -> u32 {
+    match kind {
+        NumKind::Byte => 8,
+        _ => 64,
+    }
+}
+
+fn as_bool(x: &LazValue) -> Result<bool, LazError> {
+    x.as_bool().ok_or_else(|| fail(format!("Expected bool, got {}", x.get_type())))
+}
+
+fn as_index(x: &LazValue) -> Result<usize, LazError> {
+    let i = x.as_u64().ok_or_else(|| fail(format!("Expected an index, got {}", x.get_type())))?;
+    usize::try_from(i).map_err(|_| fail(format!("Index {} is out of bounds", i)))
+}
+
+fn elements(x: &LazValue) -> Result<Vec<LazValue>, LazError> {
+    x.to_elements().ok_or_else(|| fail(format!("Expected an array, got {}", x.get_type())))
+}
+
+// Number of elements for values that can be indexed
+fn length(x: &LazValue) -> Option<usize> {
+    match x {
+        LazValue::Array(xs) | LazValue::Tuple(xs) => Some(xs.len()),
+        LazValue::Bytes(xs) => Some(xs.len()),
+        LazValue::UnsignedArray(xs) => Some(xs.len()),
+        LazValue::SignedArray(xs) => Some(xs.len()),
+        LazValue::String(s) => Some(s.chars().count()),
+        _ => None,
+    }
+}
+
+fn index(x: &LazValue, i: usize) -> Result<LazValue, LazError> {
+    let len = length(x).ok_or_else(|| fail(format!("Can't index {}", x.get_type())))?;
+    if i >= len {
+        return Err(fail(format!("Index {} is out of bounds for length {}", i, len)));
+    }
+    // Unwraps are fine, we checked the index
+    Ok(match x {
+        LazValue::Array(xs) | LazValue::Tuple(xs) => xs[i].clone(),
+        LazValue::Bytes(xs) => LazValue::Byte(xs[i]),
+        LazValue::UnsignedArray(xs) => LazValue::Unsigned(xs[i]),
+        LazValue::SignedArray(xs) => LazValue::Signed(xs[i]),
+        LazValue::String(s) => LazValue::Char(s.chars().nth(i).unwrap()),
+        _ => unreachable!(),
+    })
+}
+
+// Slices of the compact representations don't copy the data
+fn slice(x: &LazValue, start: Option<usize>, end: Option<usize>) -> Result<LazValue, LazError> {
+    let len = length(x).ok_or_else(|| fail(format!("Can't slice {}", x.get_type())))?;
+    let (start, end) = (start.unwrap_or(0), end.unwrap_or(len));
+    if start > end || end > len {
+        return Err(fail(format!("Slice {}:{} is out of bounds for length {}", start, end, len)));
+    }
+    Ok(match x {
+        LazValue::Array(xs) => LazValue::Array(xs[start..end].to_vec()),
+        LazValue::Tuple(xs) => LazValue::Tuple(xs[start..end].to_vec()),
+        LazValue::Bytes(xs) => LazValue::Bytes(xs.slice(start..end).unwrap()),
+        LazValue::UnsignedArray(xs) => LazValue::UnsignedArray(xs.slice(start..end).unwrap()),
+        LazValue::SignedArray(xs) => LazValue::SignedArray(xs.slice(start..end).unwrap()),
+        LazValue::String(s) => LazValue::String(s.chars().skip(start).take(end - start).collect()),
+        _ => unreachable!(),
+    })
+}
+
+// Numbers compare by value across kinds
+fn compare(a: &LazValue, b: &LazValue) -> Result<Ordering, LazError> {
+    let ordering = match (num_kind(a), num_kind(b)) {
+        (Some(NumKind::Float), Some(_)) | (Some(_), Some(NumKind::Float)) => {
+            a.as_f64().and_then(|a| b.as_f64().and_then(|b| a.partial_cmp(&b)))
+        }
+        (Some(_), Some(_)) => to_i128(a).and_then(|a| to_i128(b).map(|b| a.cmp(&b))),
+        _ => match (a, b) {
+            (LazValue::String(a), LazValue::String(b)) => Some(a.cmp(b)),
+            (LazValue::Char(a), LazValue::Char(b)) => Some(a.cmp(b)),
+            (LazValue::Bool(a), LazValue::Bool(b)) => Some(a.cmp(b)),
+            _ => None,
+        },
+    };
+    ordering.ok_or_else(|| fail(format!("Can't compare {} and {}", a.get_type(), b.get_type())))
+}
+
+// Structural equality. Arrays are equal no matter their representation
+fn equal(a: &LazValue, b: &LazValue) -> bool {
+    if let Ok(ordering) = compare(a, b) {
+        return ordering == Ordering::Equal;
+    }
+    if let (Some(a), Some(b)) = (a.to_elements(), b.to_elements()) {
+        return a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b));
+    }
+    match (a, b) {
+        (LazValue::Tuple(a), LazValue::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+        }
+        (LazValue::Record(a), LazValue::Record(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|((a_name, a), (b_name, b))| a_name == b_name && equal(a, b))
+        }
+        (LazValue::Matrix(a), LazValue::Matrix(b)) => {
+            a.width == b.width && a.height == b.height && a.data[..] == b.data[..]
+        }
+        _ => false,
+    }
+}
+
+fn unary(op: UnaryOp, x: LazValue) -> Result<LazValue, LazError> {
+    let invalid = || fail(format!("Can't apply {:?} to {}", op, x.get_type()));
+    match (op, &x) {
+        (UnaryOp::Not, LazValue::Bool(b)) => Ok(LazValue::Bool(!b)),
+        (UnaryOp::Neg, LazValue::Float(f)) => Ok(LazValue::Float(-f)),
+        (UnaryOp::Neg, _) => {
+            let kind = num_kind(&x).ok_or_else(invalid)?;
+            fit(-to_i128(&x).ok_or_else(invalid)?, kind.max(NumKind::Signed))
+        }
+        (UnaryOp::BitNot, LazValue::Byte(b)) => Ok(LazValue::Byte(!b)),
+        (UnaryOp::BitNot, LazValue::Unsigned(u)) => Ok(LazValue::Unsigned(!u)),
+        (UnaryOp::BitNot, LazValue::Signed(i)) => Ok(LazValue::Signed(!i)),
+        _ => Err(invalid()),
+    }
+}
+
+// Arithmetic promotes like SumNode: byte < unsigned < signed < float. Integer results that don't
+// fit in the promoted type fail. Shifts keep the type of the left side and drop the bits shifted
+// out
+fn binary(op: BinaryOp, a: &LazValue, b: &LazValue) -> Result<LazValue, LazError> {
+    let invalid = || fail(format!("Can't apply {} to {} and {}", op.symbol(), a.get_type(), b.get_type()));
+    match op {
+        BinaryOp::Eq => return Ok(LazValue::Bool(equal(a, b))),
+        BinaryOp::Ne => return Ok(LazValue::Bool(!equal(a, b))),
+        BinaryOp::Lt => return Ok(LazValue::Bool(compare(a, b)? == Ordering::Less)),
+        BinaryOp::Le => return Ok(LazValue::Bool(compare(a, b)? != Ordering::Greater)),
+        BinaryOp::Gt => return Ok(LazValue::Bool(compare(a, b)? == Ordering::Greater)),
+        BinaryOp::Ge => return Ok(LazValue::Bool(compare(a, b)? != Ordering::Less)),
+        _ => {}
+    }
+
+    match (op, a, b) {
+        (BinaryOp::Add, LazValue::String(a), LazValue::String(b)) => return Ok(LazValue::String(format!("{}{}", a, b))),
+        (BinaryOp::BitAnd, LazValue::Bool(a), LazValue::Bool(b)) => return Ok(LazValue::Bool(a & b)),
+        (BinaryOp::BitOr, LazValue::Bool(a), LazValue::Bool(b)) => return Ok(LazValue::Bool(a | b)),
+        (BinaryOp::BitXor, LazValue::Bool(a), LazValue::Bool(b)) => return Ok(LazValue::Bool(a ^ b)),
+        _ => {}
+    }
+
+    let kind = num_kind(a).ok_or_else(invalid)?.max(num_kind(b).ok_or_else(invalid)?);
+    if kind == NumKind::Float {
+        // Unwraps are fine, both are numbers
+        let (x, y) = (a.as_f64().unwrap(), b.as_f64().unwrap());
+        return match op {
+            BinaryOp::Add => Ok(LazValue::Float(x + y)),
+            BinaryOp::Sub => Ok(LazValue::Float(x - y)),
+            BinaryOp::Mul => Ok(LazValue::Float(x * y)),
+            BinaryOp::Div => Ok(LazValue::Float(x / y)),
+            BinaryOp::Rem => Ok(LazValue::Float(x % y)),
+            _ => Err(invalid()),
+        };
+    }
+
+    let (x, y) = (to_i128(a).unwrap(), to_i128(b).unwrap());
+    if (op == BinaryOp::Div || op == BinaryOp::Rem) && y == 0 {
+        return Err(fail("Division by zero"));
+    }
+    if op == BinaryOp::Shl || op == BinaryOp::Shr {
+        if y < 0 || y >= bits(num_kind(a).unwrap()) as i128 {
+            return Err(fail(format!("Can't shift {} by {}", a.get_type(), y)));
+        }
+        let x = match *a {
+            LazValue::Byte(x) => if op == BinaryOp::Shl { LazValue::Byte(x << y) } else { LazValue::Byte(x >> y) },
+            LazValue::Unsigned(x) => if op == BinaryOp::Shl { LazValue::Unsigned(x << y) } else { LazValue::Unsigned(x >> y) },
+            LazValue::Signed(x) => if op == BinaryOp::Shl { LazValue::Signed(x << y) } else { LazValue::Signed(x >> y) },
+            _ => unreachable!(),
+        };
+        return Ok(x);
+    }
+
+    let result = match op {
+        BinaryOp::Add => x.checked_add(y),
+        BinaryOp::Sub => x.checked_sub(y),
+        BinaryOp::Mul => x.checked_mul(y),
+        BinaryOp::Div => x.checked_div(y),
+        BinaryOp::Rem => x.checked_rem(y),
+        BinaryOp::BitAnd => Some(x & y),
+        BinaryOp::BitOr => Some(x | y),
+        BinaryOp::BitXor => Some(x ^ y),
+        _ => None,
+    };
+    fit(result.ok_or_else(|| fail(format!("Overflow in {} {} {}", x, op.symbol(), y)))?, kind)
+}
+
+// Converts a number to `kind`, failing if it's out of range. Floats are truncated
+fn convert(x: &LazValue, kind: NumKind) -> Result<LazValue, LazError> {
+    let invalid = || fail(format!("Can't convert {} to {:?}", x.get_type(), kind));
+    match (x, kind) {
+        (LazValue::Float(f), NumKind::Float) => Ok(LazValue::Float(*f)),
+        (LazValue::Float(f), _) => {
+            if !f.is_finite() || f.abs() >= 2f64.powi(127) {
+                return Err(invalid());
+            }
+            fit(f.trunc() as i128, kind)
+        }
+        (_, NumKind::Float) => x.as_f64().map(LazValue::Float).ok_or_else(invalid),
+        _ => fit(to_i128(x).ok_or_else(invalid)?, kind),
+    }
+}
+
+// Variables in scope, innermost last
+type Locals = Vec<(String, LazValue)>;
+
+fn call_lambda(lambda: &Expr, args: Vec<LazValue>, locals: &mut Locals) -> Result<LazValue, LazError> {
+    let (params, body) = match lambda {
+        Expr::Lambda(params, body) => (params, body),
+        _ => return Err(fail("Expected a lambda")),
+    };
+    let depth = locals.len();
+    locals.extend(params.iter().cloned().zip(args));
+    let result = eval(body, locals);
+    locals.truncate(depth);
+    result
+}
+
+fn call(function: Function, args: &[Expr], locals: &mut Locals) -> Result<LazValue, LazError> {
+    // Lambdas are evaluated by the functions that take them
+    let value = |i: usize, locals: &mut Locals| eval(&args[i], locals);
+    match function {
+        Function::Len => {
+            let x = value(0, locals)?;
+            length(&x).map(|len| LazValue::Unsigned(len as u64)).ok_or_else(|| fail(format!("{} has no length", x.get_type())))
+        }
+        Function::Map => {
+            let mut mapped = Vec::new();
+            for x in elements(&value(0, locals)?)? {
+                mapped.push(call_lambda(&args[1], vec![x], locals)?);
+            }
+            Ok(LazValue::from_elements(mapped))
+        }
+        Function::Filter => {
+            let mut kept = Vec::new();
+            for x in elements(&value(0, locals)?)? {
+                if as_bool(&call_lambda(&args[1], vec![x.clone()], locals)?)? {
+                    kept.push(x);
+                }
+            }
+            Ok(LazValue::from_elements(kept))
+        }
+        Function::Fold => {
+            let xs = elements(&value(0, locals)?)?;
+            let mut acc = value(1, locals)?;
+            for x in xs {
+                acc = call_lambda(&args[2], vec![acc, x], locals)?;
+            }
+            Ok(acc)
+        }
+        Function::Range => {
+            let first = value(0, locals)?;
+            let (start, end) = if args.len() == 1 { (LazValue::Unsigned(0), first) } else { (first, value(1, locals)?) };
+            let invalid = || fail(format!("range takes unsigned integers, got {} and {}", start.get_type(), end.get_type()));
+            let (start, end) = (start.as_u64().ok_or_else(invalid)?, end.as_u64().ok_or_else(invalid)?);
+            Ok(LazValue::UnsignedArray((start..end.max(start)).collect::<Vec<u64>>().into()))
+        }
+        Function::Bytes => {
+            let x = value(0, locals)?;
+            if let LazValue::String(ref s) = x {
+                return Ok(LazValue::Bytes(s.as_bytes().to_vec().into()));
+            }
+            let bytes = elements(&x)?.iter()
+                .map(|x| match convert(x, NumKind::Byte)? {
+                    LazValue::Byte(b) => Ok(b),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<Vec<u8>, LazError>>()?;
+            Ok(LazValue::Bytes(bytes.into()))
+        }
+        Function::Byte => convert(&value(0, locals)?, NumKind::Byte),
+        Function::Unsigned => convert(&value(0, locals)?, NumKind::Unsigned),
+        Function::Int => convert(&value(0, locals)?, NumKind::Signed),
+        Function::Float => convert(&value(0, locals)?, NumKind::Float),
+        Function::Min | Function::Max => {
+            let (a, b) = (value(0, locals)?, value(1, locals)?);
+            let a_first = compare(&a, &b)? != Ordering::Greater;
+            Ok(if a_first == (function == Function::Min) { a } else { b })
+        }
+    }
+}
+
+fn eval(expr: &Expr, locals: &mut Locals) -> Result<LazValue, LazError> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Var(name) => {
+            locals.iter().rev()
+                .find(|(local, _)| local == name)
+                .map(|(_, value)| value.clone())
+                .ok_or_else(|| fail(format!("Unknown name {:?}", name)))
+        }
+        Expr::List(items) => {
+            let items = items.iter().map(|item| eval(item, locals)).collect::<Result<Vec<_>, _>>()?;
+            Ok(LazValue::Array(items))
+        }
+        Expr::Unary(op, x) => unary(*op, eval(x, locals)?),
+        // These short circuit
+        Expr::Binary(BinaryOp::And, a, b) => {
+            Ok(LazValue::Bool(as_bool(&eval(a, locals)?)? && as_bool(&eval(b, locals)?)?))
+        }
+        Expr::Binary(BinaryOp::Or, a, b) => {
+            Ok(LazValue::Bool(as_bool(&eval(a, locals)?)? || as_bool(&eval(b, locals)?)?))
+        }
+        Expr::Binary(op, a, b) => binary(*op, &eval(a, locals)?, &eval(b, locals)?),
+        Expr::Conditional(condition, then, otherwise) => {
+            if as_bool(&eval(condition, locals)?)? {
+                eval(then, locals)
+            } else {
+                eval(otherwise, locals)
+            }
+        }
+        Expr::Index(x, i) => {
+            let x = eval(x, locals)?;
+            index(&x, as_index(&eval(i, locals)?)?)
+        }
+        Expr::Slice(x, start, end) => {
+            let x = eval(x, locals)?;
+            let start = start.as_ref().map(|i| eval(i, locals).and_then(|i| as_index(&i))).transpose()?;
+            let end = end.as_ref().map(|i| eval(i, locals).and_then(|i| as_index(&i))).transpose()?;
+            slice(&x, start, end)
+        }
+        Expr::Field(x, name) => {
+            let x = eval(x, locals)?;
+            x.field(name).cloned().ok_or_else(|| fail(format!("{} has no field {:?}", x.get_type(), name)))
+        }
+        Expr::TupleField(x, i) => match eval(x, locals)? {
+            LazValue::Tuple(mut xs) if *i < xs.len() => Ok(xs.swap_remove(*i)),
+            x => Err(fail(format!("{} has no field {}", x.get_type(), i))),
+        },
+        Expr::Call(function, args) => call(*function, args, locals),
+        Expr::Lambda(..) => Err(fail("Lambdas can only be passed to map, filter and fold")),
+    }
+}
+
+/// Evaluates an expression over its inputs, for transforms that don't deserve their own node.
+/// The expression is parsed once, when the node is created, and refers to the inputs by name.
+///
+/// The language has integer (`10`, `0xff`, `0b101`, with optional `u8`, `u64` or `i64` suffix),
+/// float, string and bool literals, lists `[a, b]`, and the operators `+ - * / %`, `& | ^ << >>`,
+/// comparisons, `&& || !`, `~` and `c ? a : b`, with C-like precedence. Arrays, tuples and strings
+/// can be indexed `x[i]` and sliced `x[i:j]`, records have fields `x.name` and tuples `x.0`.
+/// Functions are `len`, `map(xs, |x| ...)`, `filter(xs, |x| ...)`, `fold(xs, init, |acc, x| ...)`,
+/// `range`, `bytes`, the conversions `byte`, `unsigned`, `int` and `float`, and `min` and `max`.
+/// Unsuffixed integers are unsigned, so byte arithmetic is written `b ^ 0x20u8` or wrapped in
+/// `bytes(...)`
+pub struct ExpressionNode {
+    pub inputs: Vec<OutputID>,
+    input_names: Vec<String>,
+    source: String,
+    expr: Expr,
+}
+
+impl ExpressionNode {
+    /// One input per name, all disconnected. Fails if the expression doesn't parse
+    pub fn new(source: &str, input_names: Vec<String>) -> Result<ExpressionNode, LazError> {
+        for (i, name) in input_names.iter().enumerate() {
+            let reason = if !is_identifier(name) || name == "true" || name == "false" {
+                format!("{:?} is not a valid input name", name)
+            } else if input_names[..i].contains(name) {
+                format!("Input {:?} is given twice", name)
+            } else {
+                continue;
+            };
+            return Err(LazError::InvalidParams { kind: "Expression".into(), reason });
+        }
+
+        let expr = parse(source, &input_names)?;
+        Ok(ExpressionNode {
+            inputs: vec![OutputID::DISCONNECTED; input_names.len()],
+            input_names,
+            source: source.into(),
+            expr,
+        })
+    }
+}
+
+impl LazNode for ExpressionNode {
+    fn kind(&self) -> &'static str {
+        "Expression"
+    }
+    fn params(&self) -> LazValue {
+        let names = self.input_names.iter().map(|name| LazValue::String(name.clone())).collect();
+        LazValue::Record(vec![
+            ("expression".into(), LazValue::String(self.source.clone())),
+            ("inputs".into(), LazValue::Array(names)),
+        ])
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        self.inputs.iter().collect()
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        self.inputs.iter_mut().collect()
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: self.input_names.iter().map(|name| Port::new(name, LazType::Any)).collect(),
+            outputs: vec![ Port::new("Result", LazType::Any) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, self.inputs.len())?;
+
+        let mut locals = self.input_names.iter().cloned()
+            .zip(inputs.into_iter().map(|(_, value)| value))
+            .collect();
+        Ok(vec![eval(&self.expr, &mut locals)?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::assert_checks_arity;
+
+    fn run(source: &str, inputs: Vec<(&str, LazValue)>) -> Result<LazValue, LazError> {
+        let names = inputs.iter().map(|(name, _)| name.to_string()).collect();
+        let mut node = ExpressionNode::new(source, names)?;
+        let inputs = inputs.into_iter().map(|(_, value)| (OutputID::DISCONNECTED, value)).collect();
+        Ok(node.evaluate_for(inputs)?.remove(0))
+    }
+
+    fn show(source: &str) -> String {
+        run(source, vec![]).unwrap().to_string()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(show("1 + 2 * 3"), "7");
+        assert_eq!(show("(1 + 2) * 3"), "9");
+        assert_eq!(show("10 - 4 - 3"), "3");
+        assert_eq!(show("12 / 2 / 3"), "2");
+        assert_eq!(show("1 << 2 + 1"), "8");
+        assert_eq!(show("1 | 2 ^ 3 & 1"), "3");
+        assert_eq!(show("6 & 3 == 2"), "true");
+        assert_eq!(show("true || false && false"), "true");
+        assert_eq!(show("!true == false"), "true");
+        assert_eq!(show("-2 * 3"), "-6");
+        assert_eq!(show("1 < 2 ? 10 : 20"), "10");
+        assert_eq!(show("false ? 1 : true ? 2 : 3"), "2");
+        assert_eq!(show("[1, 2, 3][1:][0]"), "2");
+    }
+
+    #[test]
+    fn literal_suffixes() {
+        assert!(matches!(run("255u8", vec![]).unwrap(), LazValue::Byte(255)));
+        assert!(matches!(run("0xffu64", vec![]).unwrap(), LazValue::Unsigned(255)));
+        assert!(matches!(run("5i64", vec![]).unwrap(), LazValue::Signed(5)));
+        assert!(matches!(run("0b101", vec![]).unwrap(), LazValue::Unsigned(5)));
+        assert!(matches!(run("1_000", vec![]).unwrap(), LazValue::Unsigned(1000)));
+        assert!(matches!(run("0x41u8 ^ 0x20u8", vec![]).unwrap(), LazValue::Byte(0x61)));
+        assert_eq!(show("1.5 + 1"), "2.5");
+
+        for source in &["256u8", "1u16", "0x", "18446744073709551616"] {
+            assert!(matches!(run(source, vec![]), Err(LazError::ParseFailed { .. })), "{} parsed", source);
+        }
+    }
+
+    #[test]
+    fn nested_tuple_fields() {
+        let pair = LazValue::Tuple(vec![LazValue::Unsigned(1), LazValue::Unsigned(2)]);
+        let t = || LazValue::Tuple(vec![pair.clone(), LazValue::Unsigned(3)]);
+        assert_eq!(run("t.0.1", vec![("t", t())]).unwrap().to_string(), "2");
+        assert_eq!(run("t.1", vec![("t", t())]).unwrap().to_string(), "3");
+        assert_eq!(run("t.0.0 + 0.5", vec![("t", t())]).unwrap().to_string(), "1.5");
+        assert!(matches!(run("t.2", vec![("t", t())]), Err(LazError::ExpressionFailed(_))));
+
+        let r = LazValue::Record(vec![("inner".into(), t())]);
+        assert_eq!(run("r.inner.0.1", vec![("r", r)]).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for source in &["1 +", "(1", "x", "len(1, 2)", "|x| x", "1 2", "\"open"] {
+            assert!(matches!(run(source, vec![]), Err(LazError::ParseFailed { .. })), "{} parsed", source);
+        }
+        match run("1 + @", vec![]) {
+            Err(LazError::ParseFailed { position, .. }) => assert_eq!(position, 4),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+
+    fn parse_fails(source: &str, inputs: Vec<(&str, LazValue)>) -> bool {
+        matches!(run(source, inputs), Err(LazError::ParseFailed { .. }))
+    }
+
+    fn eval_fails(source: &str, inputs: Vec<(&str, LazValue)>) -> bool {
+        matches!(run(source, inputs), Err(LazError::ExpressionFailed(_)))
+    }
+
+    #[test]
+    fn deep_nesting_fails_to_parse() {
+        let nested = |depth: usize, open: &str, close: &str| format!("{}1{}", open.repeat(depth), close.repeat(depth));
+        assert_eq!(show(&nested(20, "(", ")")), "1");
+        assert_eq!(show(&nested(21, "-", "")), "-1");
+        for source in &[nested(100, "(", ")"), nested(100, "[", "]"), nested(100, "-", ""), nested(100, "len([", "])")] {
+            assert!(parse_fails(source, vec![]), "{} parsed", source);
+        }
+
+        // Long chains nest too, as each operator contains the ones before it
+        assert_eq!(show(&vec!["1"; 20].join(" + ")), "20");
+        assert!(parse_fails(&vec!["1"; 1000].join(" + "), vec![]));
+        assert!(parse_fails(&format!("[[1]]{}", "[0]".repeat(1000)), vec![]));
+    }
+
+    #[test]
+    fn map_filter_fold() {
+        assert_eq!(show("map([1, 2, 3], |x| x * 2)"), show("[2, 4, 6]"));
+        assert_eq!(show("filter(range(10), |x| x % 3 == 0)"), show("[0, 3, 6, 9]"));
+        assert_eq!(show("fold(range(5), 100, |acc, x| acc - x)"), "90");
+        assert_eq!(show("map([], |x| x)"), show("[]"));
+        assert!(eval_fails("filter([1, 2], |x| x)", vec![]));
+        assert!(eval_fails("map(1, |x| x)", vec![]));
+
+        for source in &["map([1], 2)", "map([1], |x, y| x)", "fold([1], 0, |acc| acc)", "filter([1])", "|x| x", "map([1], |1| 1)"] {
+            assert!(parse_fails(source, vec![]), "{} parsed", source);
+        }
+    }
+
+    #[test]
+    fn lambda_scoping() {
+        // Inner lambdas see the parameters of outer ones, and the inputs
+        let n = || ("n", LazValue::Unsigned(10));
+        assert_eq!(run("map(range(3), |x| fold(range(x + 1), n, |acc, y| acc + x * y))", vec![n()]).unwrap().to_string(), show("[10, 11, 16]"));
+        // Parameters shadow inputs and outer parameters
+        assert_eq!(run("map([1, 2], |n| n + 1)", vec![n()]).unwrap().to_string(), show("[2, 3]"));
+        assert_eq!(show("map([1], |x| map([5], |x| x)[0] + x)"), show("[6]"));
+        // Parameters are only in scope in the lambda body
+        assert!(parse_fails("map([1], |x| x)[0] + x", vec![]));
+        assert!(parse_fails("fold([1], x, |acc, x| acc)", vec![]));
+    }
+
+    #[test]
+    fn range_and_bytes() {
+        assert_eq!(show("range(3)"), show("[0, 1, 2]"));
+        assert_eq!(show("range(2, 5)"), show("[2, 3, 4]"));
+        assert_eq!(show("len(range(5, 2))"), "0");
+        assert!(eval_fails("range(-1)", vec![]));
+        assert!(eval_fails("range(1.5)", vec![]));
+
+        assert!(matches!(run("bytes(\"AB\")", vec![]).unwrap(), LazValue::Bytes(ref b) if b[..] == [0x41, 0x42]));
+        assert!(matches!(run("bytes([1, 255u8, 2i64])", vec![]).unwrap(), LazValue::Bytes(ref b) if b[..] == [1, 255, 2]));
+        assert!(matches!(run("bytes(range(3))[1]", vec![]).unwrap(), LazValue::Byte(1)));
+        assert!(eval_fails("bytes([256])", vec![]));
+        assert!(eval_fails("bytes(1)", vec![]));
+    }
+
+    #[test]
+    fn min_max_and_len() {
+        assert_eq!(show("min(3, 1.5)"), "1.5");
+        assert_eq!(show("max(2u8, 7)"), "7");
+        assert_eq!(show("min(-1, 0)"), "-1");
+        assert_eq!(show("max(\"a\", \"b\")"), show("\"b\""));
+        assert!(eval_fails("min(1, \"a\")", vec![]));
+
+        assert_eq!(show("len(\"h\u{e9}llo\")"), "5");
+        assert_eq!(show("len([1, [2, 3]])"), "2");
+        assert!(eval_fails("len(1)", vec![]));
+    }
+
+    #[test]
+    fn conversions() {
+        assert!(matches!(run("byte(255)", vec![]).unwrap(), LazValue::Byte(255)));
+        assert!(matches!(run("unsigned(7u8)", vec![]).unwrap(), LazValue::Unsigned(7)));
+        assert!(matches!(run("int(-1.7)", vec![]).unwrap(), LazValue::Signed(-1)));
+        assert!(matches!(run("int(2.9)", vec![]).unwrap(), LazValue::Signed(2)));
+        assert!(matches!(run("float(3)", vec![]).unwrap(), LazValue::Float(x) if x == 3.));
+        assert!(matches!(run("unsigned(5i64)", vec![]).unwrap(), LazValue::Unsigned(5)));
+
+        for source in &["byte(256)", "unsigned(-1)", "int(18446744073709551615)", "byte(1.0 / 0.0)", "int(\"1\")", "float(true)"] {
+            assert!(eval_fails(source, vec![]), "{} converted", source);
+        }
+    }
+
+    #[test]
+    fn indexing_and_slicing() {
+        let b = || ("b", LazValue::Bytes(vec![10, 20, 30, 40].into()));
+        assert!(matches!(run("b[1:3]", vec![b()]).unwrap(), LazValue::Bytes(ref x) if x[..] == [20, 30]));
+        assert!(matches!(run("b[:0]", vec![b()]).unwrap(), LazValue::Bytes(ref x) if x.is_empty()));
+        assert!(matches!(run("b[3]", vec![b()]).unwrap(), LazValue::Byte(40)));
+        assert!(matches!(run("b[2:][0]", vec![b()]).unwrap(), LazValue::Byte(30)));
+
+        let s = || ("s", LazValue::String("h\u{e9}llo".into()));
+        assert!(matches!(run("s[1:3]", vec![s()]).unwrap(), LazValue::String(ref x) if x == "\u{e9}l"));
+        assert!(matches!(run("s[1]", vec![s()]).unwrap(), LazValue::Char('\u{e9}')));
+        assert!(matches!(run("s[:]", vec![s()]).unwrap(), LazValue::String(ref x) if x == "h\u{e9}llo"));
+
+        for source in &["b[4]", "b[2:5]", "b[3:1]", "s[5]", "s[0:6]", "[1][1]", "b[-1]", "1[0]", "true[0:1]"] {
+            assert!(eval_fails(source, vec![b(), s()]), "{} evaluated", source);
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert!(matches!(run(r#""a\tb\n\"\\\r\0""#, vec![]).unwrap(), LazValue::String(ref x) if x == "a\tb\n\"\\\r\0"));
+        assert_eq!(show(r#""a" + "b" == "ab""#), "true");
+        for source in &[r#""\q""#, r#""\""#, r#""a"#] {
+            assert!(parse_fails(source, vec![]), "{} parsed", source);
+        }
+    }
+
+    #[test]
+    fn runtime_errors() {
+        for source in &["1 / 0", "1 % 0", "1u8 << 8", "1 << 64", "1 >> -1", "255u8 + 1u8", "0 - 1", "-9223372036854775807i64 - 2", "1 + true", "\"a\" < 1", "1 && true", "1 ? 2 : 3"] {
+            assert!(eval_fails(source, vec![]), "{} evaluated", source);
+        }
+        assert_eq!(show("1.0 / 0 > 1"), "true");
+        assert!(matches!(run("1u8 << 7", vec![]).unwrap(), LazValue::Byte(0x80)));
+        // Short circuiting skips the failing side
+        assert_eq!(show("false && 1 / 0 == 0"), "false");
+        assert_eq!(show("true ? 1 : 1 / 0"), "1");
+    }
+
+    #[test]
+    fn checks_arity() {
+        assert_checks_arity(&mut ExpressionNode::new("a + b", vec!["a".into(), "b".into()]).unwrap());
+    }
+}
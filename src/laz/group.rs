@@ -0,0 +1,399 @@
+use std::collections::HashSet;
+
+use serde::{Serialize, Deserialize};
+
+use crate::laz::types::{LazValue, LazType};
+use crate::laz::env::{LazEnv, SavedEnv, EvalControl};
+use crate::laz::nodes::{LazNode, ID, OutputID, InputID, LazError, IODescription, Port, ConstantNode, check_arity};
+use crate::laz::registry::NodeRegistry;
+use crate::laz::history::Edit;
+
+/// An input port of a group. Its value replaces the inner node `node`, which is a placeholder
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupInput {
+    pub name: String,
+    pub node: ID,
+}
+
+/// An output port of a group, giving the value of an inner output
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupOutput {
+    pub name: String,
+    pub output: OutputID,
+}
+
+/// A reusable subgraph: an inner graph with some of its nodes exposed as ports. Saved as RON, so
+/// pipelines can be kept in files and loaded as Group nodes
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GroupTemplate {
+    pub name: String,
+    pub(crate) graph: SavedEnv,
+    pub inputs: Vec<GroupInput>,
+    pub outputs: Vec<GroupOutput>,
+}
+
+impl GroupTemplate {
+    pub fn save(&self) -> Result<String, LazError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| LazError::Format(e.to_string()))
+    }
+
+    pub fn load(text: &str) -> Result<GroupTemplate, LazError> {
+        ron::de::from_str(text).map_err(|e| LazError::Format(e.to_string()))
+    }
+
+    /// The template of a group node, from its params
+    pub fn from_params(params: &LazValue) -> Result<GroupTemplate, LazError> {
+        let text = params.as_str().ok_or_else(|| LazError::InvalidParams {
+            kind: "Group".into(),
+            reason: "Expected the template as a string".into(),
+        })?;
+        GroupTemplate::load(text)
+    }
+}
+
+/// A subgraph collapsed into a single node. The inner graph is evaluated as part of evaluating the
+/// group, and keeps its own cache, so only the parts affected by changed inputs are reevaluated
+pub struct GroupNode {
+    pub inputs: Vec<OutputID>,
+    template: GroupTemplate,
+    env: LazEnv,
+}
+
+impl GroupNode {
+    /// Builds the inner graph of a template, looking up node kinds in `registry`. Inputs start
+    /// disconnected
+    pub fn new(template: GroupTemplate, registry: &NodeRegistry) -> Result<GroupNode, LazError> {
+        let env = LazEnv::from_saved(template.graph.clone(), registry)?;
+
+        for input in &template.inputs {
+            env.get_node(input.node).ok_or(LazError::NoSuchNode(input.node))?;
+        }
+        for output in &template.outputs {
+            env.output_type(output.output)?;
+        }
+
+        Ok(GroupNode {
+            inputs: vec![OutputID::DISCONNECTED; template.inputs.len()],
+            template,
+            env,
+        })
+    }
+
+    /// The edit that moves the nodes `ids` of `env` into a new group node, and the ID the group
+    /// gets. Connections into the collapsed nodes become inputs of the group, and outputs used by
+    /// the rest of the graph or displayed become its outputs. Nothing changes until the edit is
+    /// applied, so it can go through a History and be undone
+    pub fn collapse(env: &LazEnv, registry: &NodeRegistry, ids: &[ID], name: &str) -> Result<(ID, Edit), LazError> {
+        let inside = ids.iter().cloned().collect::<HashSet<_>>();
+        let mut sorted = inside.iter().cloned().collect::<Vec<_>>();
+        sorted.sort();
+        for &id in &sorted {
+            env.get_node(id).ok_or(LazError::NoSuchNode(id))?;
+        }
+
+        // Outputs used outside, and the inputs to reconnect to the group
+        let mut outputs: Vec<GroupOutput> = Vec::new();
+        let mut expose = |env: &LazEnv, output: OutputID| {
+            if let Some(outport) = outputs.iter().position(|o| o.output == output) {
+                return outport;
+            }
+            let name = env.get_node(output.node)
+                .and_then(|node| node.io_description().outputs.into_iter().nth(output.outport))
+                .map(|port| port.name)
+                .unwrap_or_default();
+            outputs.push(GroupOutput { name, output });
+            outputs.len() - 1
+        };
+
+        let mut reconnect = Vec::new();
+        for id in env.ids() {
+            if inside.contains(&id) {
+                continue;
+            }
+            // Unwrap is fine, the id comes from the env
+            let node_inputs = env.get_node(id).unwrap().inputs().into_iter().cloned().collect::<Vec<_>>();
+            for (inport, from) in node_inputs.into_iter().enumerate() {
+                if inside.contains(&from.node) {
+                    reconnect.push((InputID { node: id, inport }, expose(env, from)));
+                }
+            }
+        }
+        let displayed = env.displayed()
+            .filter(|output| inside.contains(&output.node))
+            .map(|output| expose(env, output));
+
+        let mut inner = LazEnv::default();
+        for &id in &sorted {
+            // Unwrap is fine, we checked the nodes exist
+            inner.insert_node(id, env.get_node(id).unwrap().duplicate(registry)?)?;
+        }
+
+        // Connections from outside go through placeholders, one per outside output
+        let mut sources: Vec<OutputID> = Vec::new();
+        let mut inputs: Vec<GroupInput> = Vec::new();
+        for &id in &sorted {
+            // Unwrap is fine, we just inserted the node
+            let node = inner.get_node(id).unwrap();
+            let ports = node.io_description().inputs;
+            let external = node.inputs().into_iter().cloned()
+                .enumerate()
+                .filter(|(_, from)| !inside.contains(&from.node))
+                .collect::<Vec<_>>();
+
+            for (inport, from) in external {
+                // Disconnected inputs each get their own port, so they can be connected separately
+                let existing = sources.iter().position(|&source| source == from && from != OutputID::DISCONNECTED);
+                let index = match existing {
+                    Some(index) => index,
+                    None => {
+                        let placeholder = inner.add_node(Box::new(ConstantNode { value: LazValue::Tuple(vec![]) }))?;
+                        let name = ports.get(inport).map(|port| port.name.clone()).unwrap_or_default();
+                        sources.push(from);
+                        inputs.push(GroupInput { name, node: placeholder });
+                        inputs.len() - 1
+                    }
+                };
+                inner.set_input(OutputID { node: inputs[index].node, outport: 0 }, InputID { node: id, inport })?;
+            }
+        }
+
+        let template = GroupTemplate {
+            name: name.into(),
+            graph: inner.saved(),
+            inputs,
+            outputs,
+        };
+        let group_id = env.next_id();
+        let mut edits = vec![Edit::Add { id: group_id, node: Box::new(GroupNode { inputs: sources, template, env: inner }) }];
+        edits.extend(reconnect.into_iter().map(|(to, outport)| Edit::SetInput { from: OutputID { node: group_id, outport }, to }));
+        if let Some(outport) = displayed {
+            edits.push(Edit::Display { output: Some(OutputID { node: group_id, outport }) });
+        }
+        edits.extend(sorted.into_iter().map(|id| Edit::Remove { id }));
+        Ok((group_id, Edit::Batch(edits)))
+    }
+
+    // The type the first node fed by the placeholder expects
+    fn input_type(&self, placeholder: ID) -> LazType {
+        let mut dependents = self.env.dependents(placeholder);
+        dependents.sort();
+        for id in dependents {
+            // Unwrap is fine, dependents are in the env
+            let node = self.env.get_node(id).unwrap();
+            for (inport, from) in node.inputs().into_iter().enumerate() {
+                if from.node == placeholder {
+                    if let Ok(ty) = self.env.input_type(InputID { node: id, inport }) {
+                        return ty;
+                    }
+                }
+            }
+        }
+        LazType::Any
+    }
+}
+
+impl LazNode for GroupNode {
+    fn kind(&self) -> &'static str {
+        "Group"
+    }
+    fn params(&self) -> LazValue {
+        // Unwrap is fine, templates only contain types RON can represent
+        LazValue::String(ron::ser::to_string(&self.template).unwrap())
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        self.inputs.iter().collect()
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        self.inputs.iter_mut().collect()
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: self.template.inputs.iter()
+                .map(|input| Port::new(&input.name, self.input_type(input.node)))
+                .collect(),
+            outputs: self.template.outputs.iter()
+                .map(|output| Port::new(&output.name, self.env.output_type(output.output).unwrap_or(LazType::Any)))
+                .collect(),
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        self.evaluate_with(inputs, &EvalControl::default())
+    }
+
+    // The inner graph reports progress to and is cancelled by the same control as the outer one.
+    // Its errors are wrapped in LazError::InGroup, as their node IDs are inner IDs
+    fn evaluate_with(&mut self, inputs: Vec<(OutputID, LazValue)>, control: &EvalControl) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, self.inputs.len())?;
+
+        let GroupNode { template, env, .. } = self;
+        let evaluate_inner = || {
+            for (input, (_, value)) in template.inputs.iter().zip(inputs) {
+                // Replacing a placeholder invalidates everything that depends on it, so unchanged
+                // inputs keep theirs
+                let unchanged = env.get_node(input.node).map(|node| node.params().same(&value)).unwrap_or(false);
+                if !unchanged {
+                    env.replace_node(input.node, Box::new(ConstantNode { value }))?;
+                }
+            }
+
+            template.outputs.iter()
+                .map(|output| env.evaluate_output_with(output.output, control))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        evaluate_inner().map_err(|e| LazError::InGroup { source: Box::new(e) })
+    }
+
+    // The inner nodes read lazy arrays themselves if they need to
+    fn streams_inputs(&self) -> bool {
+        true
+    }
+
+    fn has_external_changes(&mut self) -> bool {
+        self.env.refresh()
+    }
+
+    // Keeps the inner cache, and the state of the inner nodes
+    fn duplicate(&self, registry: &NodeRegistry) -> Result<Box<dyn LazNode>, LazError> {
+        Ok(Box::new(GroupNode {
+            inputs: self.inputs.clone(),
+            template: self.template.clone(),
+            env: self.env.duplicate(registry)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::byte_nodes::{ReverseNode, XorNode};
+    use crate::laz::history::History;
+    use crate::laz::nodes::assert_checks_arity;
+
+    fn bytes(data: &[u8]) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Bytes(data.to_vec().into()) })
+    }
+
+    fn evaluate_bytes(env: &mut LazEnv, output: OutputID) -> Vec<u8> {
+        env.evaluate_output_with(output, &EvalControl::default()).unwrap().as_bytes().unwrap().to_vec()
+    }
+
+    fn output(node: ID) -> OutputID {
+        OutputID { node, outport: 0 }
+    }
+
+    // data and key -> xor -> reverse -> reverse, with the first reverse displayed. Returns the IDs
+    // of the xor, the first reverse and the last reverse
+    fn pipeline(env: &mut LazEnv) -> (ID, ID, ID) {
+        let data = env.add_node(bytes(&[1, 2, 3])).unwrap();
+        let key = env.add_node(bytes(&[0xff])).unwrap();
+        let xor = env.add_node(Box::new(XorNode { input_bytes: output(data), key: output(key) })).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(xor) })).unwrap();
+        let last = env.add_node(Box::new(ReverseNode { input_bytes: output(reverse) })).unwrap();
+        env.set_displayed(Some(output(reverse)));
+        (xor, reverse, last)
+    }
+
+    #[test]
+    fn collapse_save_load_and_evaluate() {
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let mut history = History::default();
+        let (xor, reverse, last) = pipeline(&mut env);
+        assert_eq!(evaluate_bytes(&mut env, output(last)), [0xfe, 0xfd, 0xfc]);
+
+        let group = history.collapse(&mut env, &registry, &[xor, reverse], "Decode").unwrap();
+        assert!(env.get_node(xor).is_none() && env.get_node(reverse).is_none());
+        assert_eq!(env.get_node(last).unwrap().inputs(), [&output(group)]);
+        assert_eq!(env.displayed(), Some(output(group)));
+        let io = env.get_node(group).unwrap().io_description();
+        assert_eq!(io.inputs.iter().map(|port| port.name.as_str()).collect::<Vec<_>>(), ["Bytes", "Key"]);
+        assert_eq!(io.outputs.len(), 1);
+        assert_eq!(evaluate_bytes(&mut env, output(last)), [0xfe, 0xfd, 0xfc]);
+
+        let mut loaded = LazEnv::load(&env.save().unwrap(), &registry).unwrap();
+        assert_eq!(loaded.ids(), env.ids());
+        assert_eq!(evaluate_bytes(&mut loaded, output(last)), [0xfe, 0xfd, 0xfc]);
+        assert_eq!(loaded.evaluate_output_with(loaded.displayed().unwrap(), &EvalControl::default()).unwrap().as_bytes().unwrap()[..], [0xfc, 0xfd, 0xfe]);
+        assert_checks_arity(registry.load("Group", &loaded.get_node(group).unwrap().params()).unwrap().as_mut());
+
+        // Undoing puts the original nodes and connections back
+        assert!(history.undo(&mut env).unwrap());
+        assert!(env.get_node(group).is_none());
+        assert_eq!(env.get_node(last).unwrap().inputs(), [&output(reverse)]);
+        assert_eq!(env.displayed(), Some(output(reverse)));
+        assert_eq!(evaluate_bytes(&mut env, output(last)), [0xfe, 0xfd, 0xfc]);
+
+        assert!(history.redo(&mut env).unwrap());
+        assert_eq!(env.displayed(), Some(output(group)));
+        assert_eq!(evaluate_bytes(&mut env, output(last)), [0xfe, 0xfd, 0xfc]);
+    }
+
+    // The xor and first reverse of the pipeline as a group on its own
+    fn decode_group(registry: &NodeRegistry) -> GroupNode {
+        let mut env = LazEnv::default();
+        let (xor, reverse, _) = pipeline(&mut env);
+        let (_, edit) = GroupNode::collapse(&env, registry, &[xor, reverse], "Decode").unwrap();
+        match edit {
+            Edit::Batch(mut edits) => match edits.remove(0) {
+                Edit::Add { node, .. } => GroupNode::new(GroupTemplate::from_params(&node.params()).unwrap(), registry).unwrap(),
+                _ => panic!("Expected the group to be added first"),
+            },
+            _ => panic!("Expected a batch of edits"),
+        }
+    }
+
+    fn inputs(data: &[u8]) -> Vec<(OutputID, LazValue)> {
+        vec![
+            (OutputID::DISCONNECTED, LazValue::Bytes(data.to_vec().into())),
+            (OutputID::DISCONNECTED, LazValue::Bytes(vec![0xff].into())),
+        ]
+    }
+
+    #[test]
+    fn unchanged_inputs_keep_the_inner_cache() {
+        let mut group = decode_group(&NodeRegistry::builtin());
+        group.evaluate_for(inputs(&[1, 2])).unwrap();
+        let generation = group.env.generation();
+        let out = group.evaluate_for(inputs(&[1, 2])).unwrap();
+        assert_eq!(group.env.generation(), generation);
+        assert_eq!(out[0].as_bytes().unwrap()[..], [0xfd, 0xfe]);
+
+        let out = group.evaluate_for(inputs(&[1, 2, 3])).unwrap();
+        assert_ne!(group.env.generation(), generation);
+        assert_eq!(out[0].as_bytes().unwrap()[..], [0xfc, 0xfd, 0xfe]);
+    }
+
+    #[test]
+    fn inner_graph_uses_the_outer_control() {
+        let mut group = decode_group(&NodeRegistry::builtin());
+        let control = EvalControl::default();
+        group.evaluate_with(inputs(&[1, 2]), &control).unwrap();
+        let (done, total) = control.progress();
+        assert!(total > 0 && done == total);
+
+        let cancelled = EvalControl::default();
+        cancelled.cancel();
+        let err = group.evaluate_with(inputs(&[3]), &cancelled).unwrap_err();
+        assert!(matches!(err, LazError::InGroup { .. }));
+        assert!(matches!(err.root_cause(), LazError::Cancelled));
+    }
+
+    #[test]
+    fn inner_errors_point_at_the_group() {
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let mut history = History::default();
+        let (xor, reverse, last) = pipeline(&mut env);
+        let group = history.collapse(&mut env, &registry, &[xor, reverse], "Decode").unwrap();
+
+        // An empty key makes the xor inside the group fail
+        let key = env.get_node(group).unwrap().inputs()[1].node;
+        env.replace_node(key, bytes(&[])).unwrap();
+        let err = env.evaluate_output_with(output(last), &EvalControl::default()).unwrap_err();
+        assert_eq!(err.node_chain(), [last, group]);
+        assert_eq!(err.failing_node(), Some(group));
+        assert!(matches!(err.root_cause(), LazError::InvalidInputValue { .. }), "{}", err);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::laz::types::LazValue;
+use crate::laz::env::LazEnv;
+use crate::laz::nodes::{LazNode, ID, OutputID, InputID, LazError};
+use crate::laz::registry::NodeRegistry;
+use crate::laz::group::GroupNode;
+
+/// A reversible change to a LazEnv. Applying an edit gives the edit that undoes it
+pub enum Edit {
+    Add { id: ID, node: Box<dyn LazNode> },
+    Remove { id: ID },
+    /// Type checked, unless `from` is OutputID::DISCONNECTED
+    Connect { from: OutputID, to: InputID },
+    /// Like Connect, but without type checking. Undoing a Connect gives this, as the old
+    /// connection should be restored even if it doesn't type check
+    SetInput { from: OutputID, to: InputID },
+    /// Swaps in a different node under the same ID
+    Replace { id: ID, node: Box<dyn LazNode> },
+    /// Changes which output is displayed
+    Display { output: Option<OutputID> },
+    /// Edits applied in order, and undone together. If one fails, the ones before it are undone
+    Batch(Vec<Edit>),
+}
+
+impl Edit {
+    pub fn apply(self, env: &mut LazEnv) -> Result<Edit, LazError> {
+        self.try_apply(env).map_err(|(_, e)| e)
+    }
+
+    /// Like apply, but gives the edit back if it fails, so it isn't lost. A failed edit doesn't
+    /// change the env
+    pub fn try_apply(self, env: &mut LazEnv) -> Result<Edit, (Edit, LazError)> {
+        match self {
+            Edit::Add { id, node } => {
+                if let Err(e) = env.check_new_id(id) {
+                    return Err((Edit::Add { id, node }, e));
+                }
+                // Unwrap is fine, we checked the ID
+                env.insert_node(id, node).unwrap();
+                Ok(Edit::Remove { id })
+            }
+            Edit::Remove { id } => {
+                env.remove_node(id)
+                    .map(|node| Edit::Add { id, node })
+                    .map_err(|e| (Edit::Remove { id }, e))
+            }
+            Edit::Connect { from, to } => {
+                let checked = if from != OutputID::DISCONNECTED { env.check_connection(from, to) } else { Ok(()) };
+                checked
+                    .and_then(|()| env.set_input(from, to))
+                    .map(|old| Edit::SetInput { from: old, to })
+                    .map_err(|e| (Edit::Connect { from, to }, e))
+            }
+            Edit::SetInput { from, to } => {
+                env.set_input(from, to)
+                    .map(|old| Edit::SetInput { from: old, to })
+                    .map_err(|e| (Edit::SetInput { from, to }, e))
+            }
+            Edit::Replace { id, node } => {
+                if env.get_node(id).is_none() {
+                    return Err((Edit::Replace { id, node }, LazError::NoSuchNode(id)));
+                }
+                // Unwrap is fine, the node exists
+                let old = env.replace_node(id, node).unwrap();
+                Ok(Edit::Replace { id, node: old })
+            }
+            Edit::Display { output } => {
+                let old = env.displayed();
+                env.set_displayed(output);
+                Ok(Edit::Display { output: old })
+            }
+            Edit::Batch(edits) => {
+                let mut inverses = Vec::new();
+                let mut edits = edits.into_iter();
+                while let Some(edit) = edits.next() {
+                    let (failed, e) = match edit.try_apply(env) {
+                        Ok(inverse) => {
+                            inverses.push(inverse);
+                            continue;
+                        }
+                        Err(failed) => failed,
+                    };
+                    // Undoing the edits that were applied gives them back. Unwrap is fine, each
+                    // inverse is applied to the env its edit left
+                    let mut given_back = inverses.into_iter().rev()
+                        .map(|inverse| inverse.apply(env).unwrap())
+                        .collect::<Vec<_>>();
+                    given_back.reverse();
+                    given_back.push(failed);
+                    given_back.extend(edits);
+                    return Err((Edit::Batch(given_back), e));
+                }
+                inverses.reverse();
+                Ok(Edit::Batch(inverses))
+            }
+        }
+    }
+}
+
+/// Undo and redo stacks of edits to a LazEnv. Every change to the env that should be undoable has
+/// to go through here
+#[derive(Default)]
+pub struct History {
+    // Edits that undo the previous changes, latest last
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl History {
+    /// Applies an edit and records how to undo it. Clears the redo stack. If the edit fails,
+    /// nothing is changed
+    pub fn apply(&mut self, env: &mut LazEnv, edit: Edit) -> Result<(), LazError> {
+        let inverse = edit.apply(env)?;
+        self.undo_stack.push(inverse);
+        self.redo_stack.clear();
+        Ok(())
+    }
+
+    /// Returns false if there was nothing to undo. If undoing fails, the edit stays on the undo
+    /// stack
+    pub fn undo(&mut self, env: &mut LazEnv) -> Result<bool, LazError> {
+        History::step(env, &mut self.undo_stack, &mut self.redo_stack)
+    }
+
+    /// Returns false if there was nothing to redo. If redoing fails, the edit stays on the redo
+    /// stack
+    pub fn redo(&mut self, env: &mut LazEnv) -> Result<bool, LazError> {
+        History::step(env, &mut self.redo_stack, &mut self.undo_stack)
+    }
+
+    // Applies the latest edit in `from` and pushes its inverse to `to`
+    fn step(env: &mut LazEnv, from: &mut Vec<Edit>, to: &mut Vec<Edit>) -> Result<bool, LazError> {
+        let edit = match from.pop() {
+            Some(edit) => edit,
+            None => return Ok(false),
+        };
+        match edit.try_apply(env) {
+            Ok(inverse) => {
+                to.push(inverse);
+                Ok(true)
+            }
+            Err((edit, e)) => {
+                from.push(edit);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn add_node(&mut self, env: &mut LazEnv, node: Box<dyn LazNode>) -> Result<ID, LazError> {
+        let id = env.next_id();
+        self.apply(env, Edit::Add { id, node })?;
+        Ok(id)
+    }
+
+    pub fn remove_node(&mut self, env: &mut LazEnv, id: ID) -> Result<(), LazError> {
+        self.apply(env, Edit::Remove { id })
+    }
+
+    pub fn connect(&mut self, env: &mut LazEnv, from: OutputID, to: InputID) -> Result<(), LazError> {
+        self.apply(env, Edit::Connect { from, to })
+    }
+
+    pub fn disconnect(&mut self, env: &mut LazEnv, to: InputID) -> Result<(), LazError> {
+        self.apply(env, Edit::Connect { from: OutputID::DISCONNECTED, to })
+    }
+
+    /// Changes the parameters of a node (like the value of a constant) by loading a new node of the
+    /// same kind and moving the connections over
+    pub fn set_params(&mut self, env: &mut LazEnv, registry: &NodeRegistry, id: ID, params: &LazValue) -> Result<(), LazError> {
+        let old = env.get_node(id).ok_or(LazError::NoSuchNode(id))?;
+        let mut node = registry.load(old.kind(), params)?;
+        for (input, &old_input) in node.inputs_muts().into_iter().zip(old.inputs()) {
+            *input = old_input;
+        }
+        self.apply(env, Edit::Replace { id, node })
+    }
+
+    /// Moves nodes into a new group node, see GroupNode::collapse. Returns the ID of the group
+    pub fn collapse(&mut self, env: &mut LazEnv, registry: &NodeRegistry, ids: &[ID], name: &str) -> Result<ID, LazError> {
+        let (id, edit) = GroupNode::collapse(env, registry, ids, name)?;
+        self.apply(env, edit)?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::ConstantNode;
+    use crate::laz::byte_nodes::ReverseNode;
+
+    fn constant(value: u64) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Unsigned(value) })
+    }
+
+    fn bytes(data: &[u8]) -> Box<dyn LazNode> {
+        Box::new(ConstantNode { value: LazValue::Bytes(data.to_vec().into()) })
+    }
+
+    fn constant_value(env: &LazEnv, id: ID) -> String {
+        env.get_node(id).unwrap().params().to_string()
+    }
+
+    fn evaluate_bytes(env: &mut LazEnv, id: ID) -> Vec<u8> {
+        env.evaluate_node(id).unwrap()[0].as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn undo_and_redo_several_steps() {
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let mut history = History::default();
+
+        let data = history.add_node(&mut env, bytes(&[1, 2, 3])).unwrap();
+        let reverse = history.add_node(&mut env, Box::new(ReverseNode { input_bytes: OutputID::DISCONNECTED })).unwrap();
+        let to = InputID { node: reverse, inport: 0 };
+        history.connect(&mut env, OutputID { node: data, outport: 0 }, to).unwrap();
+        history.set_params(&mut env, &registry, data, &LazValue::Bytes(vec![4, 5].into())).unwrap();
+        assert_eq!(evaluate_bytes(&mut env, reverse), [5, 4]);
+
+        assert!(history.undo(&mut env).unwrap());
+        assert_eq!(evaluate_bytes(&mut env, reverse), [3, 2, 1]);
+        assert!(history.undo(&mut env).unwrap());
+        assert_eq!(env.get_node(reverse).unwrap().inputs(), [&OutputID::DISCONNECTED]);
+        assert!(history.undo(&mut env).unwrap());
+        assert!(env.get_node(reverse).is_none());
+        assert!(history.undo(&mut env).unwrap());
+        assert!(env.ids().is_empty());
+        assert!(!history.undo(&mut env).unwrap());
+
+        for _ in 0..4 {
+            assert!(history.redo(&mut env).unwrap());
+        }
+        assert!(!history.redo(&mut env).unwrap());
+        assert_eq!(env.ids(), [data, reverse]);
+        assert_eq!(evaluate_bytes(&mut env, reverse), [5, 4]);
+
+        // A new edit after undoing drops what could be redone
+        history.undo(&mut env).unwrap();
+        history.disconnect(&mut env, to).unwrap();
+        assert!(!history.can_redo());
+        assert!(history.undo(&mut env).unwrap());
+        assert_eq!(evaluate_bytes(&mut env, reverse), [3, 2, 1]);
+    }
+
+    #[test]
+    fn failed_undo_keeps_the_edit() {
+        let mut env = LazEnv::default();
+        let mut history = History::default();
+        let id = history.add_node(&mut env, constant(1)).unwrap();
+        history.remove_node(&mut env, id).unwrap();
+
+        // Undoing would add the removed node back, but the ID is taken
+        env.insert_node(id, constant(2)).unwrap();
+        assert!(matches!(history.undo(&mut env).unwrap_err(), LazError::NodeExists(_)));
+        assert!(history.can_undo());
+
+        env.remove_node(id).unwrap();
+        assert!(history.undo(&mut env).unwrap());
+        assert_eq!(constant_value(&env, id), "1");
+    }
+
+    #[test]
+    fn failed_redo_keeps_the_edit() {
+        let mut env = LazEnv::default();
+        let mut history = History::default();
+        let id = history.add_node(&mut env, constant(1)).unwrap();
+        history.undo(&mut env).unwrap();
+
+        env.insert_node(id, constant(2)).unwrap();
+        assert!(history.redo(&mut env).is_err());
+        assert!(history.can_redo());
+        assert_eq!(constant_value(&env, id), "2");
+
+        env.remove_node(id).unwrap();
+        assert!(history.redo(&mut env).unwrap());
+        assert_eq!(constant_value(&env, id), "1");
+    }
+
+    #[test]
+    fn edits_are_given_back_when_they_fail() {
+        let mut env = LazEnv::default();
+        let edit = Edit::Replace { id: ID(3), node: constant(1) };
+        let (edit, e) = edit.try_apply(&mut env).err().unwrap();
+        assert!(matches!(e, LazError::NoSuchNode(ID(3))));
+        assert!(matches!(edit, Edit::Replace { id: ID(3), .. }));
+
+        let edit = Edit::Add { id: OutputID::DISCONNECTED.node, node: constant(1) };
+        let (_, e) = edit.try_apply(&mut env).err().unwrap();
+        assert!(matches!(e, LazError::InvalidID(_)));
+        assert!(env.ids().is_empty());
+
+        // A failed batch undoes the edits before the one that failed
+        let edit = Edit::Batch(vec![
+            Edit::Add { id: ID(1), node: constant(1) },
+            Edit::Display { output: Some(OutputID { node: ID(1), outport: 0 }) },
+            Edit::Remove { id: ID(2) },
+            Edit::Add { id: ID(3), node: constant(3) },
+        ]);
+        let (edit, e) = edit.try_apply(&mut env).err().unwrap();
+        assert!(matches!(e, LazError::NoSuchNode(ID(2))));
+        assert!(env.ids().is_empty());
+        assert_eq!(env.displayed(), None);
+        match edit {
+            Edit::Batch(edits) => assert!(matches!(edits[..], [Edit::Add { .. }, Edit::Display { .. }, Edit::Remove { .. }, Edit::Add { .. }])),
+            _ => panic!("Expected the batch back"),
+        }
+    }
+}
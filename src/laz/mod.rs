@@ -1,25 +1,37 @@
-mod types;
-mod nodes;
-mod env;
-
-pub fn example_env() -> (env::LazEnv, nodes::ID) {
+pub mod types;
+pub mod nodes;
+pub mod env;
+pub mod byte_nodes;
+pub mod stats_nodes;
+pub mod decode_nodes;
+pub mod expr;
+pub mod schema;
+pub mod group;
+pub mod registry;
+pub mod history;
+pub mod worker;
+pub mod dot;
+
+/// An environment that sums the bytes of a file, and displays its digram. The file is watched, so
+/// the digram follows changes to it
+pub fn example_env() -> Result<(env::LazEnv, nodes::ID), nodes::LazError> {
+    let registry = registry::NodeRegistry::builtin();
     let mut env = env::LazEnv::default();
 
-    let path = nodes::ConstantNode {
-        value: types::LazValue::String("src/render/shaders/compiled/data.frag.spv".into()),
-    };
-
-    let path_id = env.add_node(Box::new(path));
-
-    let read_file = nodes::ReadFileNode::new(nodes::OutputID { node: path_id, outport: 0 });
+    let path = registry.load("Constant", &types::LazValue::String("src/render/mod.rs".into()))?;
+    let path_id = env.add_node(path)?;
 
-    let read_file_id = env.add_node(Box::new(read_file));
+    let read_file_params = types::LazValue::Record(vec![ ("watch".into(), types::LazValue::Bool(true)) ]);
+    let read_file_id = env.add_node(registry.load("ReadFile", &read_file_params)?)?;
+    env.connect(nodes::OutputID { node: path_id, outport: 0 }, nodes::InputID { node: read_file_id, inport: 0 })?;
 
-    let sum = nodes::SumNode {
-        input_list: nodes::OutputID { node: read_file_id, outport: 0 },
-    };
+    let sum_params = types::LazValue::Record(vec![ ("overflow".into(), types::LazValue::String("wrapping".into())) ]);
+    let sum_id = env.add_node(registry.load("Sum", &sum_params)?)?;
+    env.connect(nodes::OutputID { node: read_file_id, outport: 0 }, nodes::InputID { node: sum_id, inport: 0 })?;
 
-    let sum_id = env.add_node(Box::new(sum));
+    let digram_id = env.add_node(registry.create("Digram")?)?;
+    env.connect(nodes::OutputID { node: read_file_id, outport: 0 }, nodes::InputID { node: digram_id, inport: 0 })?;
+    env.set_displayed(Some(nodes::OutputID { node: digram_id, outport: 0 }));
 
-    (env, sum_id)
+    Ok((env, sum_id))
 }
@@ -1,42 +1,309 @@
-use crate::laz::types::LazValue;
+use crate::laz::types::{LazValue, LazType, SharedSlice, Matrix, LazyArray, ArraySource};
 use std::borrow::Cow;
-use std::io::Read;
-use std::path::PathBuf;
+use std::convert::TryFrom;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
 
-#[derive(Clone, Copy, Hash, Debug, Default, PartialEq, Eq)]
+use serde::{Serialize, Deserialize};
+
+use crate::laz::registry::NodeRegistry;
+use crate::laz::env::EvalControl;
+
+#[derive(Clone, Copy, Hash, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ID(pub u64);
 
-#[derive(Clone, Copy, Hash, Debug)]
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct OutputID { pub node: ID, pub outport: usize }
 
-#[derive(Clone, Copy, Hash, Debug)]
+impl OutputID {
+    /// Inputs that aren't connected to anything point here. Evaluating them fails with NoSuchNode
+    pub const DISCONNECTED: OutputID = OutputID { node: ID(u64::MAX), outport: 0 };
+}
+
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq)]
 pub struct InputID { pub node: ID, pub inport: usize }
 
+impl std::fmt::Display for ID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+impl std::fmt::Display for OutputID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.out{}", self.node, self.outport)
+    }
+}
+
+impl std::fmt::Display for InputID {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.in{}", self.node, self.inport)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum LazError {
-    InvalidInputType { from: OutputID, expected: String },
+    /// Context added by LazEnv: `source` happened while evaluating `node`
+    InNode { node: ID, source: Box<LazError> },
+    /// Context added by GroupNode: `source` happened in the group's inner graph, so the node IDs
+    /// in it aren't IDs of the graph the group is in
+    InGroup { source: Box<LazError> },
+    // Arc as io::Error isn't Clone
+    Io { path: PathBuf, source: Arc<std::io::Error> },
+    InvalidInputType { from: OutputID, expected: LazType, actual: LazType },
     NoSuchNode(ID),
     NoSuchOutport(OutputID),
+    NoSuchInport(InputID),
+    IndexOutOfBounds { from: OutputID, index: usize, len: usize },
+    InvalidInputValue { from: OutputID, reason: String },
+    DecodeFailed { from: OutputID, encoding: Encoding, reason: String },
+    ArithmeticOverflow { from: OutputID },
+    UnknownNodeKind(String),
+    NodeExists(ID),
+    /// Node IDs can't be the ID of OutputID::DISCONNECTED, or the one before it, as that would be
+    /// the next ID added
+    InvalidID(ID),
+    InvalidParams { kind: String, reason: String },
+    /// A saved graph couldn't be parsed or written
+    Format(String),
+    /// A node got a different number of inputs than its IODescription says
+    ArityMismatch { expected: usize, actual: usize },
+    /// A node returned a different number of outputs than its IODescription says
+    OutputCountMismatch { expected: usize, actual: usize },
+    /// The node depends on its own output
+    Cycle(ID),
+    /// Evaluation was stopped through EvalControl::cancel
+    Cancelled,
+    /// An expression couldn't be parsed. `position` is in characters from the start
+    ParseFailed { position: usize, reason: String },
+    /// An expression was parsed, but failed when evaluated
+    ExpressionFailed(String),
     Other(String),
 }
 
+impl LazError {
+    /// The node that caused the error, if the error has node context
+    pub fn failing_node(&self) -> Option<ID> {
+        self.node_chain().last().cloned()
+    }
+
+    /// The nodes that were being evaluated when the error happened, from the outermost to the
+    /// one that failed. Stops at the first group, so all IDs are in the same graph
+    pub fn node_chain(&self) -> Vec<ID> {
+        let mut chain = Vec::new();
+        let mut err = self;
+        while let LazError::InNode { node, source } = err {
+            chain.push(*node);
+            err = source;
+        }
+        chain
+    }
+
+    /// The error without any node context
+    pub fn root_cause(&self) -> &LazError {
+        match self {
+            LazError::InNode { source, .. } | LazError::InGroup { source } => source.root_cause(),
+            _ => self,
+        }
+    }
+}
+
+impl std::fmt::Display for LazError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LazError::InNode { node, .. } => write!(f, "Error evaluating node {}", node),
+            LazError::InGroup { .. } => write!(f, "Error inside the group"),
+            LazError::Io { path, .. } => write!(f, "Could not read {}", path.display()),
+            LazError::InvalidInputType { from, expected, actual } => {
+                write!(f, "Expected {} from {}, got {}", expected, from, actual)
+            }
+            LazError::NoSuchNode(id) => write!(f, "No node {}", id),
+            LazError::NoSuchOutport(output) => write!(f, "No output {}", output),
+            LazError::NoSuchInport(input) => write!(f, "No input {}", input),
+            LazError::IndexOutOfBounds { from, index, len } => {
+                write!(f, "Index {} from {} is out of bounds for length {}", index, from, len)
+            }
+            LazError::InvalidInputValue { from, reason } => write!(f, "Invalid value from {}: {}", from, reason),
+            LazError::DecodeFailed { from, encoding, reason } => {
+                write!(f, "Could not decode {:?} from {}: {}", encoding, from, reason)
+            }
+            LazError::ArityMismatch { expected, actual } => {
+                write!(f, "Expected {} inputs, got {}", expected, actual)
+            }
+            LazError::OutputCountMismatch { expected, actual } => {
+                write!(f, "Expected {} outputs, got {}", expected, actual)
+            }
+            LazError::ArithmeticOverflow { from } => write!(f, "Arithmetic overflow on {}", from),
+            LazError::Cycle(id) => write!(f, "Node {} depends on itself", id),
+            LazError::Cancelled => write!(f, "Evaluation was cancelled"),
+            LazError::ParseFailed { position, reason } => write!(f, "Parse error at character {}: {}", position, reason),
+            LazError::ExpressionFailed(reason) => write!(f, "Expression failed: {}", reason),
+            LazError::NodeExists(id) => write!(f, "Node {} already exists", id),
+            LazError::InvalidID(id) => write!(f, "{} can't be used as a node ID", id),
+            LazError::UnknownNodeKind(kind) => write!(f, "Unknown node kind {:?}", kind),
+            LazError::InvalidParams { kind, reason } => write!(f, "Invalid parameters for {}: {}", kind, reason),
+            LazError::Format(reason) => write!(f, "Invalid graph format: {}", reason),
+            LazError::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LazError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LazError::InNode { source, .. } | LazError::InGroup { source } => Some(source.as_ref()),
+            LazError::Io { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Zlib,
+    Deflate,
+    Gzip,
+    Base64,
+    Hex,
+    Utf16Le,
+    Utf16Be,
+}
+
+pub fn check_arity<T>(inputs: &[T], expected: usize) -> Result<(), LazError> {
+    if inputs.len() == expected {
+        Ok(())
+    } else {
+        Err(LazError::ArityMismatch { expected, actual: inputs.len() })
+    }
+}
+
+/// Panics unless the node fails with ArityMismatch when given one input too many or too few
+#[cfg(test)]
+pub fn assert_checks_arity(node: &mut dyn LazNode) {
+    let expected = node.io_description().inputs.len();
+    let mut counts = vec![expected + 1];
+    if expected > 0 {
+        counts.push(expected - 1);
+    }
+
+    for actual in counts {
+        let inputs = vec![(OutputID::DISCONNECTED, LazValue::Byte(0)); actual];
+        match node.evaluate_for(inputs) {
+            Err(LazError::ArityMismatch { expected: e, actual: a }) if e == expected && a == actual => {}
+            Err(e) => panic!("{} with {} inputs failed with {} instead of an arity mismatch", node.kind(), actual, e),
+            Ok(_) => panic!("{} accepted {} inputs instead of {}", node.kind(), actual, expected),
+        }
+    }
+}
+
+/// Gets an input as bytes, in any array representation
+pub fn input_bytes(input: &(OutputID, LazValue)) -> Result<SharedSlice<u8>, LazError> {
+    input.1.as_bytes().ok_or_else(||
+        LazError::InvalidInputType { from: input.0, expected: LazType::array_of(LazType::Byte), actual: input.1.get_type() }
+    )
+}
+
+/// Gets an input as a non-negative integer, for sizes and indices
+pub fn input_usize(input: &(OutputID, LazValue)) -> Result<usize, LazError> {
+    let x = input.1.as_u64().ok_or_else(||
+        LazError::InvalidInputType { from: input.0, expected: LazType::integer(), actual: input.1.get_type() }
+    )?;
+    usize::try_from(x).map_err(|_|
+        LazError::InvalidInputValue { from: input.0, reason: format!("{} doesn't fit in a usize", x) }
+    )
+}
+
 pub struct NodeInputs<'a> {
     inputs: Vec<Cow<'a, OutputID>>,
 }
 
 pub struct IODescription {
-    inputs: Vec<String>,
-    outputs: Vec<String>,
+    pub inputs: Vec<Port>,
+    pub outputs: Vec<Port>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Port {
+    pub name: String,
+    pub ty: LazType,
+}
+
+impl Port {
+    pub fn new(name: &str, ty: LazType) -> Port {
+        Port { name: name.into(), ty }
+    }
+}
+
+/// Hits and misses of a cache a node keeps itself, like ReadFileNode's file contents
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// None if the cache hasn't been used
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total == 0 { None } else { Some(self.hits as f64 / total as f64) }
+    }
 }
 
 /// Invariant: NodeInputs.inputs.len() == IODescription.inputs.len()
-pub trait LazNode {
+///
+/// Nodes are Send so independent ones can be evaluated on different threads
+pub trait LazNode: Send {
+    /// Name of the node type, used to find it in the NodeRegistry
+    fn kind(&self) -> &'static str;
+
+    /// Settings of the node that aren't inputs, in the format its NodeRegistry loader takes
+    fn params(&self) -> LazValue {
+        LazValue::Tuple(vec![])
+    }
+
     fn inputs<'a>(&'a self) -> Vec<&'a OutputID>;
     fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID>;
 
     fn io_description(&self) -> IODescription;
 
     fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError>;
+
+    /// Like evaluate_for, but nodes that can run for a long time should check `control` between
+    /// steps and fail with LazError::Cancelled once it's cancelled. LazEnv evaluates nodes with this
+    fn evaluate_with(&mut self, inputs: Vec<(OutputID, LazValue)>, _control: &EvalControl) -> Result<Vec<LazValue>, LazError> {
+        self.evaluate_for(inputs)
+    }
+
+    /// Whether evaluate_for takes lazy arrays as they are. Otherwise they're read in full before
+    /// it's called, so most nodes never see LazValue::Lazy
+    fn streams_inputs(&self) -> bool {
+        false
+    }
+
+    /// Whether the output might have changed since the last evaluation even though the inputs
+    /// haven't, for example if a file on disk has been modified
+    fn has_external_changes(&mut self) -> bool {
+        false
+    }
+
+    /// Totals since the node was created, for nodes with their own cache
+    fn cache_stats(&self) -> CacheStats {
+        CacheStats::default()
+    }
+
+    /// A copy with the same inputs, for evaluating on another thread. By default the node is loaded
+    /// from its params, so nodes that keep state between evaluations should share it with the copy
+    fn duplicate(&self, registry: &NodeRegistry) -> Result<Box<dyn LazNode>, LazError> {
+        let mut copy = registry.load(self.kind(), &self.params())?;
+        for (input, &old_input) in copy.inputs_muts().into_iter().zip(self.inputs()) {
+            *input = old_input;
+        }
+        Ok(copy)
+    }
 }
 
 pub struct ConstantNode {
@@ -44,6 +311,12 @@ pub struct ConstantNode {
 }
 
 impl LazNode for ConstantNode {
+    fn kind(&self) -> &'static str {
+        "Constant"
+    }
+    fn params(&self) -> LazValue {
+        self.value.clone()
+    }
     fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
         vec![]
     }
@@ -53,32 +326,123 @@ impl LazNode for ConstantNode {
     fn io_description(&self) -> IODescription {
         IODescription {
             inputs: vec![],
-            outputs: vec![ "Value".into() ],
+            outputs: vec![ Port::new("Value", self.value.get_type()) ],
         }
     }
 
     fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
-        assert!(inputs.is_empty());
+        check_arity(&inputs, 0)?;
 
         Ok(vec![self.value.clone()])
     }
 }
 
+// What we compare to see if a file has changed since it was read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> std::io::Result<FileStamp> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+// Reads ranges of a file when a lazy array from ReadFileNode is used. A read of the whole file is
+// kept, as other nodes using the contents usually read all of it as well
+struct FileSource {
+    path: PathBuf,
+    stamp: FileStamp,
+    contents: Mutex<Option<SharedSlice<u8>>>,
+    // Set when a read finds the file changed, so the node is reevaluated with the new file
+    changed: AtomicBool,
+}
+
+impl FileSource {
+    fn io_error(&self, e: std::io::Error) -> LazError {
+        LazError::Io { path: self.path.clone(), source: Arc::new(e) }
+    }
+}
+
+impl ArraySource for FileSource {
+    fn len(&self) -> usize {
+        self.stamp.len as usize
+    }
+
+    fn element_type(&self) -> LazType {
+        LazType::Byte
+    }
+
+    fn read(&self, range: Range<usize>) -> Result<LazValue, LazError> {
+        if let Some(contents) = &*self.contents.lock().unwrap() {
+            // Unwrap is fine, sources are only asked for ranges in bounds
+            return Ok(LazValue::Bytes(contents.slice(range).unwrap()));
+        }
+
+        // Otherwise parts of the value would come from different versions of the file
+        if FileStamp::of(&self.path).map_err(|e| self.io_error(e))? != self.stamp {
+            self.changed.store(true, Ordering::Relaxed);
+            return Err(self.io_error(std::io::Error::other("The file changed since it was opened")));
+        }
+
+        let mut f = std::fs::File::open(&self.path).map_err(|e| self.io_error(e))?;
+        f.seek(SeekFrom::Start(range.start as u64)).map_err(|e| self.io_error(e))?;
+        let mut data = vec![0; range.len()];
+        f.read_exact(&mut data).map_err(|e| self.io_error(e))?;
+
+        let data = SharedSlice::from(data);
+        if range.len() == self.len() {
+            *self.contents.lock().unwrap() = Some(data.clone());
+        }
+        Ok(LazValue::Bytes(data))
+    }
+}
+
+/// The contents are a lazy array, so only the parts other nodes use are read. The array is cached,
+/// along with the whole contents once they've been read, and only replaced if the modification
+/// time or size of the file changes. With `watch` set, the file is also checked in
+/// has_external_changes, so the node is reevaluated when the file is changed on disk, including
+/// when a missing file is created. Otherwise reading parts of a file that changed since the node
+/// was evaluated fails, unless the whole contents were already read, and the node is reevaluated
+/// at the next refresh so the evaluations after that see the new file
 pub struct ReadFileNode {
     pub file_name: OutputID,
-    file_cache: Option<(PathBuf, Box<[u8]>)>,
+    pub watch: bool,
+    file_cache: Option<Arc<FileSource>>,
+    cache_stats: CacheStats,
+    // The file name of the last evaluation and the stamp the file had then, None if it couldn't be
+    // read. Kept on failure too, so a missing file is noticed when it appears
+    watched: Option<(PathBuf, Option<FileStamp>)>,
+    // The last change has_external_changes reported, so it's only reported once
+    reported_change: Option<Option<FileStamp>>,
 }
 
 impl ReadFileNode {
     pub fn new(file_name: OutputID) -> ReadFileNode {
         ReadFileNode {
             file_name,
+            watch: false,
             file_cache: None,
+            cache_stats: CacheStats::default(),
+            watched: None,
+            reported_change: None,
         }
     }
 }
 
 impl LazNode for ReadFileNode {
+    fn kind(&self) -> &'static str {
+        "ReadFile"
+    }
+    fn params(&self) -> LazValue {
+        LazValue::Record(vec![ ("watch".into(), LazValue::Bool(self.watch)) ])
+    }
     fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
         vec![ &self.file_name ]
     }
@@ -87,41 +451,226 @@ impl LazNode for ReadFileNode {
     }
     fn io_description(&self) -> IODescription {
         IODescription {
-            inputs: vec![ "File name <string>".into() ],
-            outputs: vec![ "Contents [byte]".into() ],
+            inputs: vec![ Port::new("File name", LazType::String) ],
+            outputs: vec![ Port::new("Contents", LazType::array_of(LazType::Byte)) ],
         }
     }
 
     fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
-        assert_eq!(inputs.len(), 1);
+        check_arity(&inputs, 1)?;
 
         let path = if let LazValue::String(ref path) = inputs[0].1 {
             path
         } else {
-            Err(LazError::InvalidInputType { from: inputs[0].0, expected: "String".into() })?
+            Err(LazError::InvalidInputType { from: inputs[0].0, expected: LazType::String, actual: inputs[0].1.get_type() })?
         };
 
         let path = PathBuf::from(path);
-        if let Some((cache_path, cont)) = &self.file_cache {
-            if &path == cache_path {
-                return Ok(vec![LazValue::Array(cont.into_iter().map(|&x| LazValue::Byte(x)).collect())]);
+        let io_error = |e| LazError::Io { path: path.clone(), source: Arc::new(e) };
+
+        let stamp = FileStamp::of(&path);
+        self.watched = Some((path.clone(), stamp.as_ref().ok().cloned()));
+        let stamp = stamp.map_err(io_error)?;
+        if let Some(source) = &self.file_cache {
+            if path == source.path && stamp == source.stamp {
+                self.cache_stats.hits += 1;
+                return Ok(vec![LazValue::Lazy(LazyArray::new(source.clone()))]);
+            }
+        }
+        self.cache_stats.misses += 1;
+
+        let source = Arc::new(FileSource { path, stamp, contents: Mutex::new(None), changed: AtomicBool::new(false) });
+        self.file_cache = Some(source.clone());
+        Ok(vec![LazValue::Lazy(LazyArray::new(source))])
+    }
+
+    fn has_external_changes(&mut self) -> bool {
+        if let Some(source) = &self.file_cache {
+            if source.changed.swap(false, Ordering::Relaxed) {
+                return true;
             }
         }
+        if !self.watch {
+            return false;
+        }
+        let (path, stamp) = match &self.watched {
+            Some((path, stamp)) => (path, *stamp),
+            None => return false,
+        };
+
+        let current = FileStamp::of(path).ok();
+        if current == stamp || Some(current) == self.reported_change {
+            return false;
+        }
+        self.reported_change = Some(current);
+        true
+    }
+
+    fn cache_stats(&self) -> CacheStats {
+        self.cache_stats
+    }
+
+    // Shares the file, so a read by either is cached for both
+    fn duplicate(&self, _registry: &NodeRegistry) -> Result<Box<dyn LazNode>, LazError> {
+        Ok(Box::new(ReadFileNode {
+            file_name: self.file_name,
+            watch: self.watch,
+            file_cache: self.file_cache.clone(),
+            cache_stats: self.cache_stats,
+            watched: self.watched.clone(),
+            reported_change: self.reported_change,
+        }))
+    }
+}
+
+/// What to do when a result doesn't fit in its type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Use the smallest wider type the result fits in, up to a float if it doesn't fit in 64 bits
+    Widen,
+    /// Fail with LazError::ArithmeticOverflow
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+impl Overflow {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Overflow::Widen => "widen",
+            Overflow::Checked => "checked",
+            Overflow::Wrapping => "wrapping",
+            Overflow::Saturating => "saturating",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Overflow> {
+        match name {
+            "widen" => Some(Overflow::Widen),
+            "checked" => Some(Overflow::Checked),
+            "wrapping" => Some(Overflow::Wrapping),
+            "saturating" => Some(Overflow::Saturating),
+            _ => None,
+        }
+    }
+}
+
+// Ordered by promotion: the sum of an array has the largest kind of any of its elements
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum NumKind {
+    Byte,
+    Unsigned,
+    Signed,
+    Float,
+}
 
-        let mut f = std::fs::File::open(path.clone()).map_err(|_| LazError::Other("couldn't open file :(:( :(".into()))?;
+pub(crate) fn num_kind(x: &LazValue) -> Option<NumKind> {
+    match x {
+        LazValue::Byte(_) => Some(NumKind::Byte),
+        LazValue::Unsigned(_) => Some(NumKind::Unsigned),
+        LazValue::Signed(_) => Some(NumKind::Signed),
+        LazValue::Float(_) => Some(NumKind::Float),
+        _ => None,
+    }
+}
 
-        let mut content = Vec::new();
-        f.read_to_end(&mut content).map_err(|_| LazError::Other("when the file reading is sus".into()))?;
-        self.file_cache = Some((path, content.clone().into_boxed_slice()));
-        Ok(vec![LazValue::Array(content.into_iter().map(|x| LazValue::Byte(x)).collect())])
+// Running total of SumNode, with integers summed exactly
+struct PartialSum {
+    kind: NumKind,
+    int: i128,
+    float: f64,
+}
+
+impl PartialSum {
+    fn new() -> PartialSum {
+        PartialSum { kind: NumKind::Byte, int: 0, float: 0. }
+    }
+
+    // None if `data` isn't an array of numbers
+    fn add(&mut self, data: &LazValue) -> Option<()> {
+        // The compact representations are summed directly, so we don't have to expand them
+        match data {
+            LazValue::Bytes(data) => {
+                self.int += data.iter().map(|&x| x as i128).sum::<i128>();
+            }
+            LazValue::UnsignedArray(data) => {
+                self.kind = self.kind.max(NumKind::Unsigned);
+                self.int += data.iter().map(|&x| x as i128).sum::<i128>();
+            }
+            LazValue::SignedArray(data) => {
+                self.kind = self.kind.max(NumKind::Signed);
+                self.int += data.iter().map(|&x| x as i128).sum::<i128>();
+            }
+            LazValue::Array(data) => {
+                for x in data {
+                    self.kind = self.kind.max(num_kind(x)?);
+                    match *x {
+                        LazValue::Byte(x) => self.int += x as i128,
+                        LazValue::Unsigned(x) => self.int += x as i128,
+                        LazValue::Signed(x) => self.int += x as i128,
+                        LazValue::Float(x) => self.float += x,
+                        _ => unreachable!(),
+                    }
+                }
+            }
+            _ => return None,
+        }
+        Some(())
     }
 }
 
+/// Sums an array of numbers of any representation. The sum has the largest type of the elements,
+/// where byte < unsigned < signed < float. Integer sums are computed exactly and then fitted into
+/// the result type according to `overflow`. The sum of an empty Array is the byte 0
+///
+/// By default the sum is widened, as the sum of more than a few bytes rarely fits in a byte
 pub struct SumNode {
     pub input_list: OutputID,
+    pub overflow: Overflow,
+}
+
+impl SumNode {
+    pub fn new(input_list: OutputID) -> SumNode {
+        SumNode {
+            input_list,
+            overflow: Overflow::Widen,
+        }
+    }
+
+    // The smallest kind at least as large as `kind` that `sum` fits in
+    fn widen(sum: i128, kind: NumKind) -> NumKind {
+        let fits = |kind| match kind {
+            NumKind::Byte => u8::try_from(sum).is_ok(),
+            NumKind::Unsigned => u64::try_from(sum).is_ok(),
+            NumKind::Signed => i64::try_from(sum).is_ok(),
+            NumKind::Float => true,
+        };
+        // Unwrap is fine, everything fits in a float
+        [NumKind::Byte, NumKind::Unsigned, NumKind::Signed, NumKind::Float].iter().cloned()
+            .find(|&wider| wider >= kind && fits(wider))
+            .unwrap()
+    }
+
+    fn fit<T: std::convert::TryFrom<i128>>(&self, sum: i128, min: T, max: T, wrap: fn(i128) -> T, from: OutputID) -> Result<T, LazError> {
+        match T::try_from(sum) {
+            Ok(x) => Ok(x),
+            Err(_) => match self.overflow {
+                // Widened sums always fit
+                Overflow::Checked | Overflow::Widen => Err(LazError::ArithmeticOverflow { from }),
+                Overflow::Wrapping => Ok(wrap(sum)),
+                Overflow::Saturating => Ok(if sum < 0 { min } else { max }),
+            },
+        }
+    }
 }
 
 impl LazNode for SumNode {
+    fn kind(&self) -> &'static str {
+        "Sum"
+    }
+    fn params(&self) -> LazValue {
+        LazValue::Record(vec![ ("overflow".into(), LazValue::String(self.overflow.name().into())) ])
+    }
     fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
         vec![ &self.input_list ]
     }
@@ -130,44 +679,412 @@ impl LazNode for SumNode {
     }
     fn io_description(&self) -> IODescription {
         IODescription {
-            inputs: vec![ "List of numbers [{num}]".into() ],
-            outputs: vec![ "Sum {num}".into() ],
+            inputs: vec![ Port::new("List of numbers", LazType::array_of(LazType::num())) ],
+            outputs: vec![ Port::new("Sum", LazType::num()) ],
         }
     }
 
     fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
-        assert_eq!(inputs.len(), 1);
+        self.evaluate_with(inputs, &EvalControl::default())
+    }
+
+    fn evaluate_with(&mut self, inputs: Vec<(OutputID, LazValue)>, control: &EvalControl) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
 
-        let data = if let LazValue::Array(ref data) = inputs[0].1 {
-            data
+        let from = inputs[0].0;
+        let invalid = || LazError::InvalidInputType { from, expected: LazType::array_of(LazType::num()), actual: inputs[0].1.get_type() };
+
+        // Lazy arrays are summed a chunk at a time, so they're never in memory all at once, and
+        // summing a large file can be cancelled between chunks
+        let mut sum = PartialSum::new();
+        match inputs[0].1 {
+            LazValue::Lazy(ref data) => {
+                for chunk in data.chunks() {
+                    if control.is_cancelled() {
+                        return Err(LazError::Cancelled);
+                    }
+                    sum.add(&chunk?).ok_or_else(invalid)?;
+                }
+            }
+            ref data => sum.add(data).ok_or_else(invalid)?,
+        }
+        let PartialSum { kind, int: sum_int, float: sum_float } = sum;
+        let kind = if self.overflow == Overflow::Widen { SumNode::widen(sum_int, kind) } else { kind };
+
+        let sum = match kind {
+            NumKind::Byte => LazValue::Byte(self.fit(sum_int, u8::MIN, u8::MAX, |x| x as u8, from)?),
+            NumKind::Unsigned => LazValue::Unsigned(self.fit(sum_int, u64::MIN, u64::MAX, |x| x as u64, from)?),
+            NumKind::Signed => LazValue::Signed(self.fit(sum_int, i64::MIN, i64::MAX, |x| x as i64, from)?),
+            NumKind::Float => LazValue::Float(sum_int as f64 + sum_float),
+        };
+        Ok(vec![sum])
+    }
+
+    fn streams_inputs(&self) -> bool {
+        true
+    }
+}
+
+/// Counts how often each byte is followed by each other byte. The output is a 256x256 matrix
+/// where (x, y) is the frequency of byte y followed by byte x, normalized by the length of the
+/// input
+pub struct DigramNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for DigramNode {
+    fn kind(&self) -> &'static str {
+        "Digram"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", LazType::array_of(LazType::Byte)) ],
+            outputs: vec![ Port::new("Digram", LazType::Matrix) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        let mut out = vec![0.0f32; 256 * 256];
+        for (&first, &second) in data.iter().zip(data.iter().skip(1)) {
+            out[first as usize * 256 + second as usize] += 1.0 / (data.len() as f32);
+        }
+
+        // Unwrap is fine, the size is right
+        Ok(vec![LazValue::Matrix(Matrix::new(256, 256, out).unwrap())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(value: LazValue) -> (OutputID, LazValue) {
+        (OutputID { node: ID(0), outport: 0 }, value)
+    }
+
+    // A file in the temp directory that's removed when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = std::env::temp_dir().join(format!("laz-{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+
+        fn name(&self) -> LazValue {
+            LazValue::String(self.0.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn constant() {
+        let mut node = ConstantNode { value: LazValue::Unsigned(7) };
+        let out = node.evaluate_for(vec![]).unwrap();
+        assert!(matches!(out[..], [LazValue::Unsigned(7)]));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn read_file() {
+        let file = TempFile::new("read_file", b"hello");
+        let mut node = ReadFileNode::new(OutputID::DISCONNECTED);
+
+        let out = node.evaluate_for(vec![input(file.name())]).unwrap();
+        let contents = match out[0] {
+            LazValue::Lazy(ref data) => data.clone(),
+            ref other => panic!("Expected a lazy array, got {:?}", other),
+        };
+        assert_eq!(contents.len(), 5);
+        assert_eq!(&contents.slice(1..4).unwrap().read().unwrap().as_bytes().unwrap()[..], b"ell");
+
+        // The file hasn't changed, so the cached source is used
+        node.evaluate_for(vec![input(file.name())]).unwrap();
+        assert_eq!((node.cache_stats().hits, node.cache_stats().misses), (1, 1));
+
+        let missing = LazValue::String(file.0.with_extension("missing").to_string_lossy().into_owned());
+        assert!(matches!(node.evaluate_for(vec![input(missing)]).unwrap_err(), LazError::Io { .. }));
+        assert!(matches!(node.evaluate_for(vec![input(LazValue::Byte(1))]).unwrap_err(), LazError::InvalidInputType { .. }));
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn watched_file_that_appears_is_a_change() {
+        let path = std::env::temp_dir().join(format!("laz-{}-appears", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let name = LazValue::String(path.to_string_lossy().into_owned());
+        let mut node = ReadFileNode::new(OutputID::DISCONNECTED);
+        node.watch = true;
+
+        assert!(node.evaluate_for(vec![input(name.clone())]).is_err());
+        assert!(!node.has_external_changes());
+
+        let file = TempFile::new("appears", b"new");
+        assert_eq!(file.0, path);
+        assert!(node.has_external_changes());
+        // Only reported once
+        assert!(!node.has_external_changes());
+
+        node.evaluate_for(vec![input(name)]).unwrap();
+        assert!(!node.has_external_changes());
+        std::fs::write(&path, b"changed").unwrap();
+        assert!(node.has_external_changes());
+
+        drop(file);
+        assert!(node.has_external_changes());
+    }
+
+    #[test]
+    fn sum() {
+        let mut node = SumNode::new(OutputID::DISCONNECTED);
+        let out = node.evaluate_for(vec![input(LazValue::Bytes(vec![1, 2, 3].into()))]).unwrap();
+        assert!(matches!(out[..], [LazValue::Byte(6)]));
+        // Widened by default, so summing a file doesn't overflow
+        let out = node.evaluate_for(vec![input(LazValue::Bytes(vec![200, 100].into()))]).unwrap();
+        assert!(matches!(out[..], [LazValue::Unsigned(300)]));
+        let out = node.evaluate_for(vec![input(LazValue::UnsignedArray(vec![u64::MAX, 1].into()))]).unwrap();
+        assert!(matches!(out[..], [LazValue::Float(x)] if x == 2f64.powi(64)));
+        assert!(matches!(node.evaluate_for(vec![input(LazValue::Byte(1))]).unwrap_err(), LazError::InvalidInputType { .. }));
+        assert_checks_arity(&mut node);
+    }
+
+    // xorshift64, so the property tests are reproducible without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+
+        // Mostly small numbers, so sums both fit and overflow
+        fn number(&mut self, kinds: &[NumKind]) -> LazValue {
+            let big = self.below(4) == 0;
+            match kinds[self.below(kinds.len() as u64) as usize] {
+                NumKind::Byte => LazValue::Byte(self.next() as u8),
+                NumKind::Unsigned if big => LazValue::Unsigned(u64::MAX - self.below(1000)),
+                NumKind::Unsigned => LazValue::Unsigned(self.below(1000)),
+                NumKind::Signed if big && self.below(2) == 0 => LazValue::Signed(i64::MIN + self.below(1000) as i64),
+                NumKind::Signed if big => LazValue::Signed(i64::MAX - self.below(1000) as i64),
+                NumKind::Signed => LazValue::Signed(self.below(2000) as i64 - 1000),
+                NumKind::Float => LazValue::Float(self.below(2000) as f64 / 8. - 125.),
+            }
+        }
+    }
+
+    const OVERFLOWS: [Overflow; 4] = [Overflow::Widen, Overflow::Checked, Overflow::Wrapping, Overflow::Saturating];
+
+    // Adds the elements one by one, in the widest kind, then fits the total into the result type
+    fn reference_sum(elems: &[LazValue], overflow: Overflow) -> Option<LazValue> {
+        let kind = elems.iter().map(|x| num_kind(x).unwrap()).max().unwrap_or(NumKind::Byte);
+        // Generated floats are multiples of 1/8, so they can be summed exactly in eighths
+        let eighths = elems.iter()
+            .map(|x| match *x {
+                LazValue::Float(x) => (x * 8.) as i128,
+                LazValue::Signed(x) => 8 * x as i128,
+                _ => 8 * x.as_u64().unwrap() as i128,
+            })
+            .sum::<i128>();
+        if kind == NumKind::Float {
+            return Some(LazValue::Float(eighths as f64 / 8.));
+        }
+
+        let total = eighths / 8;
+        let (min, max) = match kind {
+            NumKind::Byte => (0, u8::MAX as i128),
+            NumKind::Unsigned => (0, u64::MAX as i128),
+            _ => (i64::MIN as i128, i64::MAX as i128),
+        };
+        let fitted = if total >= min && total <= max {
+            total
         } else {
-            Err(LazError::InvalidInputType { from: inputs[0].0, expected: "[{num}]".into() })?
+            match overflow {
+                // Bytes are never negative, and too few to overflow 64 bits. Larger sums don't fit
+                // in any integer type
+                Overflow::Widen if kind == NumKind::Byte => return Some(LazValue::Unsigned(total as u64)),
+                Overflow::Widen => return Some(LazValue::Float(total as f64)),
+                Overflow::Checked => return None,
+                Overflow::Wrapping => total,
+                Overflow::Saturating => total.max(min).min(max),
+            }
         };
+        Some(match kind {
+            NumKind::Byte => LazValue::Byte(fitted as u8),
+            NumKind::Unsigned => LazValue::Unsigned(fitted as u64),
+            _ => LazValue::Signed(fitted as i64),
+        })
+    }
+
+    fn sum_of(value: LazValue, overflow: Overflow) -> Option<LazValue> {
+        let mut node = SumNode { input_list: OutputID::DISCONNECTED, overflow };
+        match node.evaluate_for(vec![input(value)]) {
+            Ok(mut out) => Some(out.remove(0)),
+            Err(LazError::ArithmeticOverflow { .. }) => None,
+            Err(e) => panic!("Sum failed: {}", e),
+        }
+    }
+
+    fn assert_same_sum(actual: Option<LazValue>, expected: Option<LazValue>, elems: &[LazValue]) {
+        let same = match (&actual, &expected) {
+            (Some(LazValue::Float(a)), Some(LazValue::Float(b))) => (a - b).abs() <= 1e-6 * b.abs().max(1.),
+            (Some(LazValue::Byte(a)), Some(LazValue::Byte(b))) => a == b,
+            (Some(LazValue::Unsigned(a)), Some(LazValue::Unsigned(b))) => a == b,
+            (Some(LazValue::Signed(a)), Some(LazValue::Signed(b))) => a == b,
+            (None, None) => true,
+            _ => false,
+        };
+        assert!(same, "Sum of {:?} is {:?}, expected {:?}", elems, actual, expected);
+    }
+
+    #[test]
+    fn sum_matches_reference() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        let kind_sets: [&[NumKind]; 5] = [
+            &[NumKind::Byte],
+            &[NumKind::Byte, NumKind::Unsigned],
+            &[NumKind::Unsigned, NumKind::Signed],
+            &[NumKind::Byte, NumKind::Signed],
+            &[NumKind::Byte, NumKind::Unsigned, NumKind::Signed, NumKind::Float],
+        ];
+
+        for _ in 0..500 {
+            let kinds = kind_sets[rng.below(kind_sets.len() as u64) as usize];
+            let len = rng.below(20) as usize;
+            let elems = (0..len).map(|_| rng.number(kinds)).collect::<Vec<_>>();
+            for &overflow in &OVERFLOWS {
+                let actual = sum_of(LazValue::Array(elems.clone()), overflow);
+                assert_same_sum(actual, reference_sum(&elems, overflow), &elems);
+            }
+        }
+    }
+
+    #[test]
+    fn sum_is_the_same_for_every_representation() {
+        let mut rng = Rng(12345);
+        for _ in 0..100 {
+            // Empty compact arrays sum to zero of their element type, unlike an empty Array
+            let len = 1 + rng.below(50) as usize;
+            let unsigned = (0..len).map(|_| rng.below(u64::MAX / 4)).collect::<Vec<_>>();
+            let signed = (0..len).map(|_| rng.next() as i64 / 4).collect::<Vec<_>>();
+            let bytes = (0..len).map(|_| rng.next() as u8).collect::<Vec<_>>();
+
+            for &overflow in &OVERFLOWS {
+                let as_array = |xs: Vec<LazValue>| sum_of(LazValue::Array(xs), overflow);
+                assert_same_sum(
+                    sum_of(LazValue::UnsignedArray(unsigned.clone().into()), overflow),
+                    as_array(unsigned.iter().map(|&x| LazValue::Unsigned(x)).collect()),
+                    &[],
+                );
+                assert_same_sum(
+                    sum_of(LazValue::SignedArray(signed.clone().into()), overflow),
+                    as_array(signed.iter().map(|&x| LazValue::Signed(x)).collect()),
+                    &[],
+                );
+                assert_same_sum(
+                    sum_of(LazValue::Bytes(bytes.clone().into()), overflow),
+                    as_array(bytes.iter().map(|&x| LazValue::Byte(x)).collect()),
+                    &[],
+                );
+            }
+        }
+    }
+
+    struct InMemory(Vec<u8>);
 
-        let sum_bytes: Option<u8> =
-                data.iter()
-                .map(|x| if let LazValue::Byte(b) = x { Some(b) } else { None } )
-                .fold(Some(0u8), |acc, b| Some(acc?.overflowing_add(*b?).0));
-        if let Some(sum) = sum_bytes {
-            return Ok(vec![LazValue::Byte(sum)]);
+    impl ArraySource for InMemory {
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+        fn element_type(&self) -> LazType {
+            LazType::Byte
+        }
+        fn read(&self, range: Range<usize>) -> Result<LazValue, LazError> {
+            Ok(LazValue::Bytes(self.0[range].to_vec().into()))
         }
+    }
+
+    #[test]
+    fn sum_of_lazy_array_matches_sum_of_bytes() {
+        let mut rng = Rng(777);
+        // Several chunks, the last one partial
+        let data = (0..LazyArray::CHUNK_LEN * 2 + 123).map(|_| rng.next() as u8).collect::<Vec<_>>();
+        let lazy = LazValue::Lazy(LazyArray::new(Arc::new(InMemory(data.clone()))));
 
-        let sum_unsigned: Option<u64> =
-                data.iter()
-                .map(|x| if let LazValue::Unsigned(b) = x { Some(b) } else { None } )
-                .fold(Some(0), |acc, b| Some(acc? + b?));
-        if let Some(sum) = sum_unsigned {
-            return Ok(vec![LazValue::Unsigned(sum)]);
+        for &overflow in &OVERFLOWS {
+            assert_same_sum(
+                sum_of(lazy.clone(), overflow),
+                sum_of(LazValue::Bytes(data.clone().into()), overflow),
+                &[],
+            );
         }
+    }
+
+    // Cancels `control` when the first chunk is read, like pressing Ctrl+C while a node runs
+    struct CancelsOnRead {
+        control: Arc<EvalControl>,
+        reads: Mutex<usize>,
+    }
 
-        let sum_signed: Option<i64> =
-                data.iter()
-                .map(|x| if let LazValue::Signed(b) = x { Some(b) } else { None } )
-                .fold(Some(0), |acc, b| Some(acc? + b?));
-        if let Some(sum) = sum_signed {
-            return Ok(vec![LazValue::Signed(sum)]);
+    impl ArraySource for CancelsOnRead {
+        fn len(&self) -> usize {
+            LazyArray::CHUNK_LEN * 3
+        }
+        fn element_type(&self) -> LazType {
+            LazType::Byte
         }
+        fn read(&self, range: Range<usize>) -> Result<LazValue, LazError> {
+            *self.reads.lock().unwrap() += 1;
+            self.control.cancel();
+            Ok(LazValue::Bytes(vec![1; range.len()].into()))
+        }
+    }
 
-        Err(LazError::InvalidInputType { from: inputs[0].0, expected: "Could not sum".into() })
+    #[test]
+    fn cancelling_stops_a_running_sum() {
+        let control = Arc::new(EvalControl::default());
+        let source = Arc::new(CancelsOnRead { control: control.clone(), reads: Mutex::new(0) });
+        let mut node = SumNode::new(OutputID::DISCONNECTED);
+
+        let err = node.evaluate_with(vec![input(LazValue::Lazy(LazyArray::new(source.clone())))], &control).unwrap_err();
+        assert!(matches!(err, LazError::Cancelled));
+        assert_eq!(*source.reads.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn digram() {
+        let mut node = DigramNode { input_bytes: OutputID::DISCONNECTED };
+        let out = node.evaluate_for(vec![input(LazValue::Bytes(vec![1, 2, 1, 2].into()))]).unwrap();
+        let digram = match out[0] {
+            LazValue::Matrix(ref m) => m.clone(),
+            ref other => panic!("Expected a matrix, got {:?}", other),
+        };
+        assert_eq!((digram.width, digram.height), (256, 256));
+        // 1 followed by 2 twice, 2 followed by 1 once, out of 4 bytes
+        assert_eq!(digram.row(1).unwrap()[2], 0.5);
+        assert_eq!(digram.row(2).unwrap()[1], 0.25);
+        assert_eq!(digram.data.iter().sum::<f32>(), 0.75);
+        assert_checks_arity(&mut node);
     }
 }
@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use crate::laz::types::LazValue;
+use crate::laz::nodes::{self, LazNode, OutputID, LazError, Overflow};
+use crate::laz::byte_nodes;
+use crate::laz::stats_nodes;
+use crate::laz::decode_nodes::{self, Compression};
+use crate::laz::expr::ExpressionNode;
+use crate::laz::schema::StructNode;
+use crate::laz::group::{GroupNode, GroupTemplate};
+
+// Nodes are loaded with all inputs disconnected
+const NO_INPUT: OutputID = OutputID::DISCONNECTED;
+
+/// Builds a node from the output of LazNode::params. Inputs are left at their defaults. The registry
+/// is for nodes containing other nodes, like groups
+pub type NodeLoader = fn(&LazValue, &NodeRegistry) -> Result<Box<dyn LazNode>, LazError>;
+
+pub struct NodeType {
+    pub kind: &'static str,
+    /// For grouping node types, for example in a palette
+    pub category: &'static str,
+    pub description: &'static str,
+    pub default_params: fn() -> LazValue,
+    pub loader: NodeLoader,
+}
+
+impl NodeType {
+    pub fn create(&self, registry: &NodeRegistry) -> Result<Box<dyn LazNode>, LazError> {
+        (self.loader)(&(self.default_params)(), registry)
+    }
+}
+
+/// Maps node kinds to functions creating them, so graphs can be rebuilt by name
+#[derive(Default)]
+pub struct NodeRegistry {
+    types: HashMap<&'static str, NodeType>,
+}
+
+impl std::fmt::Debug for NodeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.types().iter().map(|t| t.kind)).finish()
+    }
+}
+
+fn no_params() -> LazValue {
+    LazValue::Tuple(vec![])
+}
+
+fn invalid_params(kind: &str, reason: &str) -> LazError {
+    LazError::InvalidParams { kind: kind.into(), reason: reason.into() }
+}
+
+fn string_param<'a>(params: &'a LazValue, kind: &str, name: &str) -> Result<&'a str, LazError> {
+    params.field(name).and_then(|x| x.as_str())
+        .ok_or_else(|| invalid_params(kind, &format!("Expected string field {:?}", name)))
+}
+
+fn bool_param(params: &LazValue, kind: &str, name: &str) -> Result<bool, LazError> {
+    params.field(name).and_then(|x| x.as_bool())
+        .ok_or_else(|| invalid_params(kind, &format!("Expected bool field {:?}", name)))
+}
+
+fn string_list_param(params: &LazValue, kind: &str, name: &str) -> Result<Vec<String>, LazError> {
+    params.field(name)
+        .and_then(|x| x.to_elements())
+        .and_then(|xs| xs.iter().map(|x| x.as_str().map(String::from)).collect())
+        .ok_or_else(|| invalid_params(kind, &format!("Expected field {:?} to be a list of strings", name)))
+}
+
+impl NodeRegistry {
+    /// A registry with every node type in laz
+    pub fn builtin() -> NodeRegistry {
+        let mut registry = NodeRegistry::default();
+
+        registry.register(NodeType {
+            kind: "Constant",
+            category: "Input",
+            description: "A fixed value",
+            default_params: || LazValue::Unsigned(0),
+            loader: |params, _| Ok(Box::new(nodes::ConstantNode { value: params.clone() })),
+        });
+        registry.register(NodeType {
+            kind: "ReadFile",
+            category: "Input",
+            description: "The contents of a file, optionally reloaded when it changes",
+            default_params: || nodes::ReadFileNode::new(NO_INPUT).params(),
+            loader: |params, _| {
+                let mut node = nodes::ReadFileNode::new(NO_INPUT);
+                // Graphs saved before watching was added have no params
+                if params.field("watch").is_some() {
+                    node.watch = bool_param(params, "ReadFile", "watch")?;
+                }
+                Ok(Box::new(node))
+            },
+        });
+        registry.register(NodeType {
+            kind: "Sum",
+            category: "Arithmetic",
+            description: "Sum of a list of numbers",
+            default_params: || nodes::SumNode::new(NO_INPUT).params(),
+            loader: |params, _| {
+                let overflow = string_param(params, "Sum", "overflow")?;
+                let overflow = Overflow::from_name(overflow).ok_or_else(|| invalid_params("Sum", "Unknown overflow mode"))?;
+                Ok(Box::new(nodes::SumNode { input_list: NO_INPUT, overflow }))
+            },
+        });
+        registry.register(NodeType {
+            kind: "Digram",
+            category: "Visualization",
+            description: "256x256 matrix of how often each byte follows each other byte",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(nodes::DigramNode { input_bytes: NO_INPUT })),
+        });
+
+        registry.register(NodeType {
+            kind: "Slice",
+            category: "Bytes",
+            description: "A range of bytes, given by offset and length",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::SliceNode { input_bytes: NO_INPUT, offset: NO_INPUT, length: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Split",
+            category: "Bytes",
+            description: "The bytes before and from an offset",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::SplitNode { input_bytes: NO_INPUT, offset: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Concat",
+            category: "Bytes",
+            description: "Two byte buffers joined together",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::ConcatNode { first: NO_INPUT, second: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Xor",
+            category: "Bytes",
+            description: "Xor with a repeating key",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::XorNode { input_bytes: NO_INPUT, key: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Stride",
+            category: "Bytes",
+            description: "Every nth byte, for deinterleaving",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::StrideNode { input_bytes: NO_INPUT, stride: NO_INPUT, offset: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Reverse",
+            category: "Bytes",
+            description: "The bytes in reverse order",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::ReverseNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "EndianSwap",
+            category: "Bytes",
+            description: "Reverses the byte order of each word",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::EndianSwapNode { input_bytes: NO_INPUT, width: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Chunk",
+            category: "Bytes",
+            description: "Splits bytes into fixed size chunks",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(byte_nodes::ChunkNode { input_bytes: NO_INPUT, size: NO_INPUT })),
+        });
+
+        registry.register(NodeType {
+            kind: "Histogram",
+            category: "Statistics",
+            description: "How many times each byte value occurs",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::HistogramNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Entropy",
+            category: "Statistics",
+            description: "Shannon entropy in bits per byte",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::EntropyNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "ChiSquare",
+            category: "Statistics",
+            description: "Chi-square of the byte distribution against uniform",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::ChiSquareNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Mean",
+            category: "Statistics",
+            description: "Arithmetic mean of the bytes",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::MeanNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "SerialCorrelation",
+            category: "Statistics",
+            description: "Correlation between consecutive bytes",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::SerialCorrelationNode { input_bytes: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "MonteCarloPi",
+            category: "Statistics",
+            description: "Monte Carlo estimate of pi, using the bytes as coordinates",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(stats_nodes::MonteCarloPiNode { input_bytes: NO_INPUT })),
+        });
+
+        registry.register(NodeType {
+            kind: "Decompress",
+            category: "Decoding",
+            description: "Decompresses zlib, raw deflate or gzip data",
+            default_params: || LazValue::Record(vec![ ("compression".into(), LazValue::String(Compression::Zlib.name().into())) ]),
+            loader: |params, _| {
+                let compression = string_param(params, "Decompress", "compression")?;
+                let compression = Compression::from_name(compression).ok_or_else(|| invalid_params("Decompress", "Unknown compression"))?;
+                Ok(Box::new(decode_nodes::DecompressNode { input_bytes: NO_INPUT, compression }))
+            },
+        });
+        registry.register(NodeType {
+            kind: "Base64Decode",
+            category: "Decoding",
+            description: "Decodes base64 text",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(decode_nodes::Base64DecodeNode { input_text: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "HexDecode",
+            category: "Decoding",
+            description: "Decodes hex text",
+            default_params: no_params,
+            loader: |_, _| Ok(Box::new(decode_nodes::HexDecodeNode { input_text: NO_INPUT })),
+        });
+        registry.register(NodeType {
+            kind: "Utf16Decode",
+            category: "Decoding",
+            description: "Decodes UTF-16 into a string",
+            default_params: || LazValue::Record(vec![ ("big_endian".into(), LazValue::Bool(false)) ]),
+            loader: |params, _| {
+                let big_endian = bool_param(params, "Utf16Decode", "big_endian")?;
+                Ok(Box::new(decode_nodes::Utf16DecodeNode { input_bytes: NO_INPUT, big_endian }))
+            },
+        });
+        registry.register(NodeType {
+            kind: "Struct",
+            category: "Decoding",
+            description: "Parses binary data into a record, following a schema of fields",
+            default_params: || LazValue::Record(vec![ ("schema".into(), LazValue::String(
+                r#"[(name: "length", type: U16(Big)), (name: "payload", type: Bytes(Field("length")))]"#.into()
+            )) ]),
+            loader: |params, _| {
+                let schema = string_param(params, "Struct", "schema")?;
+                Ok(Box::new(StructNode::new(schema)?))
+            },
+        });
+
+        registry.register(NodeType {
+            kind: "Expression",
+            category: "Scripting",
+            description: "Evaluates an expression over the inputs, such as map(x, |b| b ^ 0x20u8)",
+            default_params: || LazValue::Record(vec![
+                ("expression".into(), LazValue::String("x".into())),
+                ("inputs".into(), LazValue::Array(vec![ LazValue::String("x".into()) ])),
+            ]),
+            loader: |params, _| {
+                let expression = string_param(params, "Expression", "expression")?;
+                let inputs = string_list_param(params, "Expression", "inputs")?;
+                Ok(Box::new(ExpressionNode::new(expression, inputs)?))
+            },
+        });
+
+        registry.register(NodeType {
+            kind: "Group",
+            category: "Groups",
+            description: "A subgraph collapsed into a single node",
+            default_params: || LazValue::String(GroupTemplate::default().save().unwrap_or_default()),
+            loader: |params, registry| {
+                let template = GroupTemplate::from_params(params)?;
+                Ok(Box::new(GroupNode::new(template, registry)?))
+            },
+        });
+
+        registry
+    }
+
+    /// Replaces any type with the same kind
+    pub fn register(&mut self, node_type: NodeType) {
+        self.types.insert(node_type.kind, node_type);
+    }
+
+    pub fn get(&self, kind: &str) -> Result<&NodeType, LazError> {
+        self.types.get(kind).ok_or_else(|| LazError::UnknownNodeKind(kind.into()))
+    }
+
+    /// All node types, sorted by category and then kind
+    pub fn types(&self) -> Vec<&NodeType> {
+        let mut types = self.types.values().collect::<Vec<_>>();
+        types.sort_by_key(|t| (t.category, t.kind));
+        types
+    }
+
+    /// Creates a node with default parameters
+    pub fn create(&self, kind: &str) -> Result<Box<dyn LazNode>, LazError> {
+        self.get(kind)?.create(self)
+    }
+
+    pub fn load(&self, kind: &str, params: &LazValue) -> Result<Box<dyn LazNode>, LazError> {
+        (self.get(kind)?.loader)(params, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_type_creates_a_disconnected_node_of_its_kind() {
+        let registry = NodeRegistry::builtin();
+        for node_type in registry.types() {
+            let node = registry.create(node_type.kind).unwrap();
+            assert_eq!(node.kind(), node_type.kind);
+            assert!(node.inputs().iter().all(|&&input| input == OutputID::DISCONNECTED));
+            assert!(!node_type.category.is_empty() && !node_type.description.is_empty());
+        }
+    }
+
+    #[test]
+    fn types_are_sorted_by_category_then_kind() {
+        let registry = NodeRegistry::builtin();
+        let keys = registry.types().iter().map(|t| (t.category, t.kind)).collect::<Vec<_>>();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+
+    #[test]
+    fn unknown_kinds_fail() {
+        let registry = NodeRegistry::builtin();
+        assert!(matches!(registry.create("Nope"), Err(LazError::UnknownNodeKind(_))));
+        assert!(registry.load("Nope", &no_params()).is_err());
+    }
+}
@@ -0,0 +1,533 @@
+use std::ops::Range;
+
+use serde::{Serialize, Deserialize};
+
+use crate::laz::types::{LazValue, LazType, SharedSlice, LazyArray};
+use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, check_arity, input_bytes};
+
+// Bytes read at once from lazy inputs, so consecutive small fields don't each cause a read
+const READ_WINDOW: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// A length, count or offset: a constant, or the value of an integer field parsed earlier
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Size {
+    Fixed(u64),
+    /// Looked up in the innermost struct first, then in the structs containing it
+    Field(String),
+    /// Until the end of the input. Arrays of fixed size elements take as many as fit. Not allowed
+    /// as an offset
+    Rest,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FieldType {
+    U8,
+    U16(Endian),
+    U32(Endian),
+    U64(Endian),
+    I8,
+    I16(Endian),
+    I32(Endian),
+    I64(Endian),
+    F32(Endian),
+    F64(Endian),
+    /// Raw bytes, as a slice of the input
+    Bytes(Size),
+    /// Elements one after the other. Each has to take at least one byte
+    Array(Box<FieldType>, Size),
+    Struct(Vec<Field>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    /// Where the field starts, from the start of the struct. By default right after the previous
+    /// field
+    #[serde(default)]
+    pub offset: Option<Size>,
+}
+
+/// Describes a binary structure as a list of fields, like a minimal Kaitai Struct. Written in RON,
+/// for example `[(name: "length", type: U16(Big)), (name: "payload", type: Bytes(Field("length")))]`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Schema {
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    /// Parses and checks a schema. Sizes may only refer to integer fields that come before them
+    pub fn load(text: &str) -> Result<Schema, LazError> {
+        let schema: Schema = ron::de::from_str(text).map_err(|e| invalid_schema(e.to_string()))?;
+        check_fields(&schema.fields, &mut Vec::new())?;
+        Ok(schema)
+    }
+
+    /// The type of the parsed record
+    pub fn record_type(&self) -> LazType {
+        record_type(&self.fields)
+    }
+}
+
+fn invalid_schema(reason: String) -> LazError {
+    LazError::InvalidParams { kind: "Struct".into(), reason }
+}
+
+// `scopes` has the integer fields before the current one, of each struct from the outermost
+fn check_fields(fields: &[Field], scopes: &mut Vec<Vec<String>>) -> Result<(), LazError> {
+    scopes.push(Vec::new());
+    let mut names = Vec::new();
+    let result = fields.iter().try_for_each(|field| {
+        if names.contains(&&field.name) {
+            return Err(invalid_schema(format!("Field {:?} appears twice", field.name)));
+        }
+        names.push(&field.name);
+
+        match &field.offset {
+            Some(Size::Rest) => return Err(invalid_schema(format!("Offset of {:?} can't be Rest", field.name))),
+            Some(size) => check_size(size, scopes)?,
+            None => {}
+        }
+        check_type(&field.ty, scopes)?;
+        if is_integer(&field.ty) {
+            // Unwrap is fine, we pushed a scope above
+            scopes.last_mut().unwrap().push(field.name.clone());
+        }
+        Ok(())
+    });
+    scopes.pop();
+    result
+}
+
+fn check_type(ty: &FieldType, scopes: &mut Vec<Vec<String>>) -> Result<(), LazError> {
+    match ty {
+        FieldType::Bytes(size) => check_size(size, scopes),
+        FieldType::Array(elem, size) => {
+            check_size(size, scopes)?;
+            check_type(elem, scopes)
+        }
+        FieldType::Struct(fields) => check_fields(fields, scopes),
+        _ => Ok(()),
+    }
+}
+
+fn check_size(size: &Size, scopes: &[Vec<String>]) -> Result<(), LazError> {
+    match size {
+        Size::Field(name) if !scopes.iter().any(|scope| scope.contains(name)) => {
+            Err(invalid_schema(format!("No integer field {:?} before it is used", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn is_integer(ty: &FieldType) -> bool {
+    matches!(ty,
+        FieldType::U8 | FieldType::U16(_) | FieldType::U32(_) | FieldType::U64(_)
+        | FieldType::I8 | FieldType::I16(_) | FieldType::I32(_) | FieldType::I64(_))
+}
+
+fn field_type(ty: &FieldType) -> LazType {
+    match ty {
+        FieldType::U8 => LazType::Byte,
+        FieldType::U16(_) | FieldType::U32(_) | FieldType::U64(_) => LazType::Unsigned,
+        FieldType::I8 | FieldType::I16(_) | FieldType::I32(_) | FieldType::I64(_) => LazType::Signed,
+        FieldType::F32(_) | FieldType::F64(_) => LazType::Float,
+        FieldType::Bytes(_) => LazType::array_of(LazType::Byte),
+        FieldType::Array(elem, _) => LazType::array_of(field_type(elem)),
+        FieldType::Struct(fields) => record_type(fields),
+    }
+}
+
+fn record_type(fields: &[Field]) -> LazType {
+    LazType::Record(fields.iter().map(|field| (field.name.clone(), field_type(&field.ty))).collect())
+}
+
+// Size in bytes of types that always take the same space
+fn fixed_size(ty: &FieldType) -> Option<usize> {
+    match ty {
+        FieldType::U8 | FieldType::I8 => Some(1),
+        FieldType::U16(_) | FieldType::I16(_) => Some(2),
+        FieldType::U32(_) | FieldType::I32(_) | FieldType::F32(_) => Some(4),
+        FieldType::U64(_) | FieldType::I64(_) | FieldType::F64(_) => Some(8),
+        _ => None,
+    }
+}
+
+fn read_uint(bytes: &[u8], endian: Endian) -> u64 {
+    match endian {
+        Endian::Little => bytes.iter().rev().fold(0, |x, &b| x << 8 | b as u64),
+        Endian::Big => bytes.iter().fold(0, |x, &b| x << 8 | b as u64),
+    }
+}
+
+// Sign extends an integer of `width` bytes
+fn to_signed(x: u64, width: usize) -> i64 {
+    let shift = 64 - 8 * width as u32;
+    ((x << shift) as i64) >> shift
+}
+
+enum Input {
+    Bytes(SharedSlice<u8>),
+    // The last window read, and where it starts
+    Lazy(LazyArray, Option<(usize, SharedSlice<u8>)>),
+}
+
+impl Input {
+    fn len(&self) -> usize {
+        match self {
+            Input::Bytes(data) => data.len(),
+            Input::Lazy(data, _) => data.len(),
+        }
+    }
+
+    // The range has to be in bounds
+    fn read(&mut self, range: Range<usize>) -> Result<SharedSlice<u8>, LazError> {
+        let (data, window) = match self {
+            // Unwrap is fine, the range is in bounds
+            Input::Bytes(data) => return Ok(data.slice(range).unwrap()),
+            Input::Lazy(data, window) => (data, window),
+        };
+
+        if let Some((start, bytes)) = window {
+            if range.start >= *start && range.end <= *start + bytes.len() {
+                // Unwrap is fine, we checked the window contains the range
+                return Ok(bytes.slice(range.start - *start .. range.end - *start).unwrap());
+            }
+        }
+        let end = range.end.max(range.start + READ_WINDOW).min(data.len());
+        // Unwraps are fine, the range is in bounds and the array contains bytes
+        let bytes = data.slice(range.start..end).unwrap().read()?.as_bytes().unwrap();
+        let slice = bytes.slice(0..range.len()).unwrap();
+        *window = Some((range.start, bytes));
+        Ok(slice)
+    }
+
+    // Raw bytes fields don't copy the input, and stay lazy for lazy inputs
+    fn slice(&self, range: Range<usize>) -> LazValue {
+        // Unwraps are fine, the range has to be in bounds
+        match self {
+            Input::Bytes(data) => LazValue::Bytes(data.slice(range).unwrap()),
+            Input::Lazy(data, _) => LazValue::Lazy(data.slice(range).unwrap()),
+        }
+    }
+}
+
+struct Parser {
+    input: Input,
+    from: OutputID,
+    // The fields parsed so far of each struct being parsed, from the outermost
+    scopes: Vec<Vec<(String, LazValue)>>,
+}
+
+impl Parser {
+    fn fail(&self, path: &str, reason: String) -> LazError {
+        LazError::InvalidInputValue { from: self.from, reason: format!("{}: {}", path, reason) }
+    }
+
+    fn check_bounds(&self, path: &str, start: usize, len: usize) -> Result<Range<usize>, LazError> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.input.len() => Ok(start..end),
+            _ => Err(self.fail(path, format!("{} bytes at {} don't fit in {} bytes of input", len, start, self.input.len()))),
+        }
+    }
+
+    fn resolve(&self, path: &str, size: &Size, pos: usize) -> Result<usize, LazError> {
+        match size {
+            Size::Fixed(x) => Ok(*x as usize),
+            Size::Field(name) => {
+                let value = self.scopes.iter().rev()
+                    .find_map(|scope| scope.iter().find(|(field, _)| field == name).map(|(_, x)| x));
+                // Checking the schema made sure the field exists, but it might be negative
+                value.and_then(|x| x.as_u64())
+                    .map(|x| x as usize)
+                    .ok_or_else(|| self.fail(path, format!("Field {:?} isn't a non-negative integer", name)))
+            }
+            Size::Rest => Ok(self.input.len().saturating_sub(pos)),
+        }
+    }
+
+    fn parse_struct(&mut self, path: &str, fields: &[Field], start: usize) -> Result<(LazValue, usize), LazError> {
+        self.scopes.push(Vec::new());
+        let mut pos = start;
+        for field in fields {
+            let field_path = if path.is_empty() { field.name.clone() } else { format!("{}.{}", path, field.name) };
+            if let Some(offset) = &field.offset {
+                pos = start.saturating_add(self.resolve(&field_path, offset, pos)?);
+            }
+            let (value, end) = self.parse(&field_path, &field.ty, pos)?;
+            pos = end;
+            // Unwrap is fine, we pushed a scope above
+            self.scopes.last_mut().unwrap().push((field.name.clone(), value));
+        }
+        // Unwrap is fine, we pushed a scope above
+        Ok((LazValue::Record(self.scopes.pop().unwrap()), pos))
+    }
+
+    // The value at `pos`, and where it ends
+    fn parse(&mut self, path: &str, ty: &FieldType, pos: usize) -> Result<(LazValue, usize), LazError> {
+        if let Some(width) = fixed_size(ty) {
+            let range = self.check_bounds(path, pos, width)?;
+            let bytes = self.input.read(range.clone())?;
+            return Ok((number(ty, &bytes), range.end));
+        }
+
+        match ty {
+            FieldType::Bytes(size) => {
+                let len = self.resolve(path, size, pos)?;
+                let range = self.check_bounds(path, pos, len)?;
+                Ok((self.input.slice(range.clone()), range.end))
+            }
+            FieldType::Array(elem, size) => self.parse_array(path, elem, size, pos),
+            FieldType::Struct(fields) => self.parse_struct(path, fields, pos),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_array(&mut self, path: &str, elem: &FieldType, size: &Size, pos: usize) -> Result<(LazValue, usize), LazError> {
+        // Counts are checked up front, so a corrupt count fails before parsing anything. Elements
+        // take at least a byte, so there can't be more than there are bytes left
+        let remaining = self.input.len().saturating_sub(pos);
+        let count = match (size, fixed_size(elem)) {
+            (Size::Rest, Some(width)) => Some(remaining / width),
+            (Size::Rest, None) => None,
+            (size, Some(width)) => {
+                let count = self.resolve(path, size, pos)?;
+                self.check_bounds(path, pos, count.saturating_mul(width))?;
+                Some(count)
+            }
+            (size, None) => {
+                let count = self.resolve(path, size, pos)?;
+                if count > remaining {
+                    return Err(self.fail(path, format!("{} elements at {} don't fit in {} bytes of input", count, pos, self.input.len())));
+                }
+                Some(count)
+            }
+        };
+
+        if let (FieldType::U8, Some(count)) = (elem, count) {
+            let range = self.check_bounds(path, pos, count)?;
+            return Ok((self.input.slice(range.clone()), range.end));
+        }
+
+        let mut elems = Vec::new();
+        let mut pos = pos;
+        loop {
+            let done = match count {
+                Some(count) => elems.len() == count,
+                None => pos >= self.input.len(),
+            };
+            if done {
+                break;
+            }
+            let (value, end) = self.parse(&format!("{}[{}]", path, elems.len()), elem, pos)?;
+            // Elements that take no space would never reach the end of the input, and would make
+            // the count above meaningless
+            if end == pos {
+                return Err(self.fail(path, "Array elements can't be empty".into()));
+            }
+            elems.push(value);
+            pos = end;
+        }
+        Ok((LazValue::from_elements(elems), pos))
+    }
+}
+
+fn number(ty: &FieldType, bytes: &[u8]) -> LazValue {
+    match *ty {
+        FieldType::U8 => LazValue::Byte(bytes[0]),
+        FieldType::I8 => LazValue::Signed(bytes[0] as i8 as i64),
+        FieldType::U16(endian) | FieldType::U32(endian) | FieldType::U64(endian) => {
+            LazValue::Unsigned(read_uint(bytes, endian))
+        }
+        FieldType::I16(endian) | FieldType::I32(endian) | FieldType::I64(endian) => {
+            LazValue::Signed(to_signed(read_uint(bytes, endian), bytes.len()))
+        }
+        FieldType::F32(endian) => LazValue::Float(f32::from_bits(read_uint(bytes, endian) as u32) as f64),
+        FieldType::F64(endian) => LazValue::Float(f64::from_bits(read_uint(bytes, endian))),
+        _ => unreachable!(),
+    }
+}
+
+/// Parses bytes into a record, following a schema. The first output is the whole record, and the
+/// others are its top-level fields, so header fields can be connected to nodes like Slice directly.
+/// Lazy inputs are only read where the schema has fields
+pub struct StructNode {
+    pub input_bytes: OutputID,
+    source: String,
+    schema: Schema,
+}
+
+impl StructNode {
+    pub fn new(source: &str) -> Result<StructNode, LazError> {
+        Ok(StructNode {
+            input_bytes: OutputID::DISCONNECTED,
+            source: source.into(),
+            schema: Schema::load(source)?,
+        })
+    }
+}
+
+impl LazNode for StructNode {
+    fn kind(&self) -> &'static str {
+        "Struct"
+    }
+    fn params(&self) -> LazValue {
+        LazValue::Record(vec![ ("schema".into(), LazValue::String(self.source.clone())) ])
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        let fields = self.schema.fields.iter().map(|field| Port::new(&field.name, field_type(&field.ty)));
+        IODescription {
+            inputs: vec![ Port::new("Bytes", LazType::array_of(LazType::Byte)) ],
+            outputs: std::iter::once(Port::new("Record", self.schema.record_type())).chain(fields).collect(),
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let input = match inputs[0].1 {
+            LazValue::Lazy(ref data) if data.element_type() == LazType::Byte => Input::Lazy(data.clone(), None),
+            ref data => Input::Bytes(input_bytes(&(inputs[0].0, data.clone().materialize()?))?),
+        };
+        let mut parser = Parser { input, from: inputs[0].0, scopes: Vec::new() };
+        let (record, _) = parser.parse_struct("", &self.schema.fields, 0)?;
+
+        let fields = match record {
+            LazValue::Record(ref fields) => fields.iter().map(|(_, x)| x.clone()).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        Ok(std::iter::once(record).chain(fields).collect())
+    }
+
+    fn streams_inputs(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::assert_checks_arity;
+
+    fn parse(schema: &str, data: &[u8]) -> Result<LazValue, LazError> {
+        let mut node = StructNode::new(schema)?;
+        let out = node.evaluate_for(vec![(OutputID::DISCONNECTED, LazValue::Bytes(data.to_vec().into()))])?;
+        Ok(out.into_iter().next().unwrap())
+    }
+
+    fn show(schema: &str, data: &[u8]) -> String {
+        parse(schema, data).unwrap().to_string()
+    }
+
+    // The reason of a failed parse, which starts with the path of the field
+    fn failure(schema: &str, data: &[u8]) -> String {
+        match parse(schema, data) {
+            Err(LazError::InvalidInputValue { reason, .. }) => reason,
+            other => panic!("Expected the parse to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn endianness() {
+        let schema = r#"[
+            (name: "big", type: U16(Big)),
+            (name: "little", type: U16(Little)),
+            (name: "u32", type: U32(Little)),
+            (name: "u64", type: U64(Big)),
+            (name: "f32", type: F32(Big)),
+            (name: "f64", type: F64(Little)),
+        ]"#;
+        let mut data = vec![1, 2, 1, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0];
+        data.extend_from_slice(&1.5f32.to_be_bytes());
+        data.extend_from_slice(&(-0.25f64).to_le_bytes());
+        assert_eq!(show(schema, &data), "{big: 258, little: 513, u32: 1, u64: 256, f32: 1.5, f64: -0.25}");
+    }
+
+    #[test]
+    fn sign_extension() {
+        let schema = r#"[
+            (name: "i8", type: I8),
+            (name: "i16", type: I16(Big)),
+            (name: "i32", type: I32(Little)),
+            (name: "i64", type: I64(Big)),
+            (name: "positive", type: I16(Little)),
+            (name: "u8", type: U8),
+        ]"#;
+        let mut data = vec![0x80, 0xff, 0xfe, 0xfe, 0xff, 0xff, 0xff];
+        data.extend_from_slice(&[0xff; 8]);
+        data.extend_from_slice(&[0xff, 0x7f, 0xff]);
+        assert_eq!(show(schema, &data), "{i8: -128, i16: -2, i32: -2, i64: -1, positive: 32767, u8: 0xff}");
+    }
+
+    #[test]
+    fn nested_field_lookup() {
+        // Inner fields shadow outer ones, and outer ones are visible inside
+        let schema = r#"[
+            (name: "n", type: U8),
+            (name: "pad", type: U8),
+            (name: "items", type: Array(Struct([
+                (name: "len", type: U8),
+                (name: "data", type: Bytes(Field("len"))),
+                (name: "tail", type: Bytes(Field("pad"))),
+            ]), Field("n"))),
+            (name: "inner", type: Struct([
+                (name: "n", type: U8),
+                (name: "data", type: Bytes(Field("n"))),
+                (name: "at", type: U8, offset: Some(Field("pad"))),
+            ])),
+        ]"#;
+        let data = [2, 1, 1, 0xa, 0, 0, 0xb, 1, 0xc];
+        assert_eq!(
+            show(schema, &data),
+            "{n: 0x02, pad: 0x01, items: [{len: 0x01, data: [0x0a], tail: [0x00]}, {len: 0x00, data: [], tail: [0x0b]}], \
+             inner: {n: 0x01, data: [0x0c], at: 0x0c}}",
+        );
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        assert!(failure(r#"[(name: "x", type: U32(Big))]"#, &[1, 2, 3]).starts_with("x: 4 bytes at 0"));
+        assert!(failure(r#"[(name: "x", type: U8, offset: Some(Fixed(9)))]"#, &[1, 2, 3]).starts_with("x: "));
+        assert!(failure(r#"[(name: "n", type: U8), (name: "x", type: Bytes(Field("n")))]"#, &[5, 1]).starts_with("x: "));
+        assert!(failure(r#"[(name: "x", type: Array(U16(Big), Fixed(3)))]"#, &[0; 5]).starts_with("x: "));
+        let nested = r#"[(name: "s", type: Struct([(name: "a", type: U8), (name: "b", type: U16(Big))]))]"#;
+        assert!(failure(nested, &[1, 2]).starts_with("s.b: "));
+        let elements = r#"[(name: "xs", type: Array(Struct([(name: "a", type: U16(Big))]), Rest))]"#;
+        assert!(failure(elements, &[0, 1, 2]).starts_with("xs[1].a: "));
+        assert!(matches!(parse(r#"[(name: "x", type: Bytes(Field("y")))]"#, &[]), Err(LazError::InvalidParams { .. })));
+    }
+
+    #[test]
+    fn empty_elements_and_huge_counts_fail() {
+        // Would otherwise make a trillion empty records
+        let huge = r#"[(name: "xs", type: Array(Struct([]), Fixed(1000000000000)))]"#;
+        assert!(failure(huge, &[0; 16]).starts_with("xs: 1000000000000 elements"));
+        let counted = r#"[(name: "xs", type: Array(Bytes(Fixed(0)), Fixed(3)))]"#;
+        assert_eq!(failure(counted, &[0; 16]), "xs: Array elements can't be empty");
+        let rest = r#"[(name: "xs", type: Array(Struct([]), Rest))]"#;
+        assert_eq!(failure(rest, &[0]), "xs: Array elements can't be empty");
+
+        // Empty arrays of empty elements are fine
+        assert_eq!(show(r#"[(name: "xs", type: Array(Struct([]), Fixed(0)))]"#, &[]), "{xs: []}");
+        assert_eq!(show(r#"[(name: "xs", type: Array(Bytes(Fixed(2)), Rest))]"#, &[1, 2, 3, 4]), "{xs: [[0x01, 0x02], [0x03, 0x04]]}");
+    }
+
+    #[test]
+    fn checks_arity() {
+        assert_checks_arity(&mut StructNode::new(r#"[(name: "x", type: U8)]"#).unwrap());
+    }
+}
@@ -0,0 +1,359 @@
+// Statistics over byte buffers, following the definitions used by `ent`
+
+use crate::laz::types::{LazValue, LazType};
+use crate::laz::nodes::{LazNode, OutputID, LazError, IODescription, Port, check_arity, input_bytes};
+
+fn bytes_type() -> LazType {
+    LazType::array_of(LazType::Byte)
+}
+
+fn byte_counts(data: &[u8]) -> [u64; 256] {
+    let mut counts = [0; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    counts
+}
+
+fn empty_input(from: OutputID) -> LazError {
+    LazError::InvalidInputValue { from, reason: "Empty input".into() }
+}
+
+/// How many times each byte value occurs
+pub struct HistogramNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for HistogramNode {
+    fn kind(&self) -> &'static str {
+        "Histogram"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Counts", LazType::array_of(LazType::Unsigned)) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        Ok(vec![LazValue::UnsignedArray(byte_counts(&data).to_vec().into())])
+    }
+}
+
+/// Shannon entropy in bits per byte, between 0 and 8. Empty input has zero entropy
+pub struct EntropyNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for EntropyNode {
+    fn kind(&self) -> &'static str {
+        "Entropy"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Entropy", LazType::Float) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+
+        let n = data.len() as f64;
+        let entropy = byte_counts(&data).iter()
+            .filter(|&&count| count != 0)
+            .map(|&count| {
+                let p = count as f64 / n;
+                -p * p.log2()
+            })
+            .sum::<f64>();
+
+        Ok(vec![LazValue::Float(entropy)])
+    }
+}
+
+/// Chi-square statistic of the byte counts against a uniform distribution (255 degrees of freedom)
+pub struct ChiSquareNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for ChiSquareNode {
+    fn kind(&self) -> &'static str {
+        "ChiSquare"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Chi-square", LazType::Float) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+        if data.is_empty() {
+            return Err(empty_input(inputs[0].0));
+        }
+
+        let expected = data.len() as f64 / 256.;
+        let chi_square = byte_counts(&data).iter()
+            .map(|&count| {
+                let delta = count as f64 - expected;
+                delta * delta / expected
+            })
+            .sum::<f64>();
+
+        Ok(vec![LazValue::Float(chi_square)])
+    }
+}
+
+/// Arithmetic mean of the bytes. 127.5 for random data
+pub struct MeanNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for MeanNode {
+    fn kind(&self) -> &'static str {
+        "Mean"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Mean", LazType::Float) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+        if data.is_empty() {
+            return Err(empty_input(inputs[0].0));
+        }
+
+        let sum = data.iter().map(|&b| b as u64).sum::<u64>();
+
+        Ok(vec![LazValue::Float(sum as f64 / data.len() as f64)])
+    }
+}
+
+/// Correlation between each byte and the next, wrapping around at the end. Close to 0 for random
+/// data
+pub struct SerialCorrelationNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for SerialCorrelationNode {
+    fn kind(&self) -> &'static str {
+        "SerialCorrelation"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Correlation", LazType::Float) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+        if data.is_empty() {
+            return Err(empty_input(inputs[0].0));
+        }
+
+        let n = data.len() as f64;
+        let next = data.iter().skip(1).chain(data.iter().take(1));
+        let sum_products = data.iter().zip(next).map(|(&a, &b)| a as f64 * b as f64).sum::<f64>();
+        let sum = data.iter().map(|&b| b as f64).sum::<f64>();
+        let sum_squares = data.iter().map(|&b| b as f64 * b as f64).sum::<f64>();
+
+        let denominator = n * sum_squares - sum * sum;
+        if denominator == 0. {
+            return Err(LazError::InvalidInputValue {
+                from: inputs[0].0,
+                reason: "Serial correlation is undefined for constant input".into(),
+            });
+        }
+
+        Ok(vec![LazValue::Float((n * sum_products - sum * sum) / denominator)])
+    }
+}
+
+/// Estimates pi by treating each 6 bytes as a point (two 24 bit big endian coordinates) in a
+/// square and counting how many fall inside the inscribed circle. Trailing bytes are ignored
+pub struct MonteCarloPiNode {
+    pub input_bytes: OutputID,
+}
+
+impl LazNode for MonteCarloPiNode {
+    fn kind(&self) -> &'static str {
+        "MonteCarloPi"
+    }
+    fn inputs<'a>(&'a self) -> Vec<&'a OutputID> {
+        vec![ &self.input_bytes ]
+    }
+    fn inputs_muts<'a>(&'a mut self) -> Vec<&'a mut OutputID> {
+        vec![ &mut self.input_bytes ]
+    }
+    fn io_description(&self) -> IODescription {
+        IODescription {
+            inputs: vec![ Port::new("Bytes", bytes_type()) ],
+            outputs: vec![ Port::new("Pi estimate", LazType::Float) ],
+        }
+    }
+
+    fn evaluate_for(&mut self, inputs: Vec<(OutputID, LazValue)>) -> Result<Vec<LazValue>, LazError> {
+        check_arity(&inputs, 1)?;
+
+        let data = input_bytes(&inputs[0])?;
+        if data.len() < 6 {
+            return Err(LazError::InvalidInputValue { from: inputs[0].0, reason: "Need at least 6 bytes".into() });
+        }
+
+        let coord = |bytes: &[u8]| bytes.iter().fold(0u64, |acc, &b| acc * 256 + b as u64);
+        let radius_squared = (256u64.pow(3) - 1).pow(2);
+
+        let points = data.chunks_exact(6);
+        let total = points.len();
+        let inside = points
+            .filter(|point| {
+                let x = coord(&point[..3]);
+                let y = coord(&point[3..]);
+                x * x + y * y <= radius_squared
+            })
+            .count();
+
+        Ok(vec![LazValue::Float(4. * inside as f64 / total as f64)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::{ID, assert_checks_arity};
+
+    fn run(node: &mut dyn LazNode, data: &[u8]) -> Result<f64, LazError> {
+        let input = (OutputID { node: ID(0), outport: 0 }, LazValue::Bytes(data.to_vec().into()));
+        let out = node.evaluate_for(vec![input])?;
+        match out[0] {
+            LazValue::Float(x) => Ok(x),
+            ref other => panic!("Expected a float, got {:?}", other),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    fn every_byte() -> Vec<u8> {
+        (0..=255).collect()
+    }
+
+    #[test]
+    fn histogram() {
+        let mut node = HistogramNode { input_bytes: OutputID::DISCONNECTED };
+        let input = (OutputID { node: ID(0), outport: 0 }, LazValue::Bytes(vec![1, 1, 255].into()));
+        let out = node.evaluate_for(vec![input]).unwrap();
+        let counts = match out[0] {
+            LazValue::UnsignedArray(ref counts) => counts.to_vec(),
+            ref other => panic!("Expected counts, got {:?}", other),
+        };
+        assert_eq!(counts.len(), 256);
+        assert_eq!((counts[0], counts[1], counts[255]), (0, 2, 1));
+        assert_eq!(counts.iter().sum::<u64>(), 3);
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn entropy() {
+        let mut node = EntropyNode { input_bytes: OutputID::DISCONNECTED };
+        assert_close(run(&mut node, &every_byte()).unwrap(), 8.);
+        assert_close(run(&mut node, &[0, 0, 1, 1]).unwrap(), 1.);
+        assert_close(run(&mut node, &[0, 1, 2, 3]).unwrap(), 2.);
+        assert_close(run(&mut node, &[7; 100]).unwrap(), 0.);
+        assert_close(run(&mut node, &[]).unwrap(), 0.);
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn chi_square() {
+        let mut node = ChiSquareNode { input_bytes: OutputID::DISCONNECTED };
+        assert_close(run(&mut node, &every_byte()).unwrap(), 0.);
+        // Expected count is 1, so 255² for the zero byte and 1 for each of the other 255 values
+        assert_close(run(&mut node, &[0; 256]).unwrap(), 65280.);
+        assert!(run(&mut node, &[]).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn mean() {
+        let mut node = MeanNode { input_bytes: OutputID::DISCONNECTED };
+        assert_close(run(&mut node, &every_byte()).unwrap(), 127.5);
+        assert_close(run(&mut node, &[1, 2, 3, 4]).unwrap(), 2.5);
+        assert_close(run(&mut node, &[255; 3]).unwrap(), 255.);
+        assert!(run(&mut node, &[]).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn serial_correlation() {
+        let mut node = SerialCorrelationNode { input_bytes: OutputID::DISCONNECTED };
+        assert_close(run(&mut node, &[0, 255, 0, 255]).unwrap(), -1.);
+        // Products 1·2 + 2·3 + 3·1 = 11, sum 6, sum of squares 14: (3·11 - 36) / (3·14 - 36)
+        assert_close(run(&mut node, &[1, 2, 3]).unwrap(), -0.5);
+        assert!(run(&mut node, &[9; 10]).is_err());
+        assert!(run(&mut node, &[]).is_err());
+        assert_checks_arity(&mut node);
+    }
+
+    #[test]
+    fn monte_carlo_pi() {
+        let mut node = MonteCarloPiNode { input_bytes: OutputID::DISCONNECTED };
+        // The origin is inside the circle, the far corner isn't. The trailing byte is ignored
+        let mut data = vec![0; 6];
+        data.extend_from_slice(&[0xff; 7]);
+        assert_close(run(&mut node, &data).unwrap(), 2.);
+        // (r, 0) is exactly on the circle and counts as inside
+        assert_close(run(&mut node, &[0xff, 0xff, 0xff, 0, 0, 0]).unwrap(), 4.);
+        assert!(run(&mut node, &[0; 5]).is_err());
+        assert_checks_arity(&mut node);
+    }
+}
@@ -1,11 +1,665 @@
-#[derive(Clone, Debug)]
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use serde::{Serialize, Deserialize};
+
+use crate::laz::nodes::LazError;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum LazValue {
     Byte(u8),
     Char(char),
     Unsigned(u64),
     Signed(i64),
+    Float(f64),
+    Bool(bool),
 
     Array(Vec<LazValue>),
     String(String),
+    Tuple(Vec<LazValue>),
+    /// Named fields, in order
+    Record(Vec<(String, LazValue)>),
+    Matrix(Matrix),
+
+    // Compact representations of arrays. These have the same LazType as the corresponding Array
+    Bytes(SharedSlice<u8>),
+    UnsignedArray(SharedSlice<u64>),
+    SignedArray(SharedSlice<i64>),
+
+    /// An array read on demand. Can't be saved, so it's only produced by evaluating nodes
+    #[serde(skip)]
+    Lazy(LazyArray),
+}
+
+/// An immutable view into reference counted storage. Cloning and slicing doesn't copy the data
+#[derive(Clone)]
+pub struct SharedSlice<T> {
+    data: Arc<[T]>,
+    range: Range<usize>,
+}
+
+impl<T> SharedSlice<T> {
+    pub fn new(data: Arc<[T]>) -> SharedSlice<T> {
+        let range = 0..data.len();
+        SharedSlice { data, range }
+    }
+
+    /// Range is relative to this slice. Returns None if out of bounds
+    pub fn slice(&self, range: Range<usize>) -> Option<SharedSlice<T>> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        Some(SharedSlice {
+            data: self.data.clone(),
+            range: self.range.start + range.start .. self.range.start + range.end,
+        })
+    }
+}
+
+impl<T> Deref for SharedSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data[self.range.clone()]
+    }
 }
 
+impl<T> From<Vec<T>> for SharedSlice<T> {
+    fn from(data: Vec<T>) -> SharedSlice<T> {
+        SharedSlice::new(data.into())
+    }
+}
+
+// Serialized as a plain list
+impl<T: Serialize> Serialize for SharedSlice<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SharedSlice<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<T>::deserialize(deserializer).map(SharedSlice::from)
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for SharedSlice<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Produces the elements of a LazyArray, for example by reading them from a file
+pub trait ArraySource: Send + Sync {
+    fn len(&self) -> usize;
+    fn element_type(&self) -> LazType;
+    /// The elements in `range` as an array value, preferably in a compact representation. The
+    /// range is always in bounds
+    fn read(&self, range: Range<usize>) -> Result<LazValue, LazError>;
+}
+
+/// A view into an array whose elements are only produced when they're read. Slicing doesn't read
+/// anything, so a slice of a large file only reads the part that's used
+#[derive(Clone)]
+pub struct LazyArray {
+    source: Arc<dyn ArraySource>,
+    range: Range<usize>,
+}
+
+impl LazyArray {
+    /// Elements read at once by chunks
+    pub const CHUNK_LEN: usize = 1 << 16;
+
+    pub fn new(source: Arc<dyn ArraySource>) -> LazyArray {
+        let range = 0..source.len();
+        LazyArray { source, range }
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn element_type(&self) -> LazType {
+        self.source.element_type()
+    }
+
+    /// Range is relative to this array. Returns None if out of bounds
+    pub fn slice(&self, range: Range<usize>) -> Option<LazyArray> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        Some(LazyArray {
+            source: self.source.clone(),
+            range: self.range.start + range.start .. self.range.start + range.end,
+        })
+    }
+
+    /// Reads all elements
+    pub fn read(&self) -> Result<LazValue, LazError> {
+        self.source.read(self.range.clone())
+    }
+
+    /// Reads the elements CHUNK_LEN at a time, so they can be processed without holding all of
+    /// them in memory
+    pub fn chunks(&self) -> impl Iterator<Item = Result<LazValue, LazError>> + '_ {
+        self.range.clone()
+            .step_by(LazyArray::CHUNK_LEN)
+            .map(move |start| self.source.read(start..(start + LazyArray::CHUNK_LEN).min(self.range.end)))
+    }
+}
+
+impl std::fmt::Debug for LazyArray {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LazyArray({:?} of [{}])", self.range, self.element_type())
+    }
+}
+
+/// A dense 2D grid of floats, stored row by row. f32 as that's what we upload to the GPU
+#[derive(Clone, Debug, Serialize)]
+pub struct Matrix {
+    pub width: usize,
+    pub height: usize,
+    pub data: SharedSlice<f32>,
+}
+
+impl Matrix {
+    pub fn new(width: usize, height: usize, data: Vec<f32>) -> Option<Matrix> {
+        if width.checked_mul(height) != Some(data.len()) {
+            return None;
+        }
+        Some(Matrix { width, height, data: data.into() })
+    }
+
+    pub fn row(&self, y: usize) -> Option<&[f32]> {
+        if y >= self.height {
+            return None;
+        }
+        Some(&self.data[y * self.width .. (y + 1) * self.width])
+    }
+}
+
+// Goes through Matrix::new, so a saved matrix with the wrong amount of data fails to load instead
+// of panicking when it's read
+impl<'de> Deserialize<'de> for Matrix {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(rename = "Matrix")]
+        struct SavedMatrix {
+            width: usize,
+            height: usize,
+            data: Vec<f32>,
+        }
+
+        let SavedMatrix { width, height, data } = SavedMatrix::deserialize(deserializer)?;
+        let len = data.len();
+        Matrix::new(width, height, data).ok_or_else(|| serde::de::Error::custom(
+            format!("A {}x{} matrix can't have {} elements", width, height, len)
+        ))
+    }
+}
+
+impl LazValue {
+    /// The most specific type describing this value. Arrays are typed by their first element, and
+    /// as `[Any]` if empty
+    pub fn get_type(&self) -> LazType {
+        match self {
+            LazValue::Byte(_) => LazType::Byte,
+            LazValue::Char(_) => LazType::Char,
+            LazValue::Unsigned(_) => LazType::Unsigned,
+            LazValue::Signed(_) => LazType::Signed,
+            LazValue::Float(_) => LazType::Float,
+            LazValue::Bool(_) => LazType::Bool,
+            LazValue::Array(elems) => {
+                let inner = elems.first().map(|x| x.get_type()).unwrap_or(LazType::Any);
+                LazType::Array(Box::new(inner))
+            }
+            LazValue::String(_) => LazType::String,
+            LazValue::Tuple(elems) => LazType::Tuple(elems.iter().map(|x| x.get_type()).collect()),
+            LazValue::Record(fields) => {
+                LazType::Record(fields.iter().map(|(name, x)| (name.clone(), x.get_type())).collect())
+            }
+            LazValue::Matrix(_) => LazType::Matrix,
+            LazValue::Bytes(_) => LazType::array_of(LazType::Byte),
+            LazValue::UnsignedArray(_) => LazType::array_of(LazType::Unsigned),
+            LazValue::SignedArray(_) => LazType::array_of(LazType::Signed),
+            LazValue::Lazy(xs) => LazType::array_of(xs.element_type()),
+        }
+    }
+
+    /// Whether the values are the same, in the same representation. Floats compare by their bits.
+    /// Lazy arrays aren't read, so they're only the same if they view the same part of one source
+    pub fn same(&self, other: &LazValue) -> bool {
+        let all_same = |a: &[LazValue], b: &[LazValue]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same(b));
+        match (self, other) {
+            (LazValue::Byte(a), LazValue::Byte(b)) => a == b,
+            (LazValue::Char(a), LazValue::Char(b)) => a == b,
+            (LazValue::Unsigned(a), LazValue::Unsigned(b)) => a == b,
+            (LazValue::Signed(a), LazValue::Signed(b)) => a == b,
+            (LazValue::Float(a), LazValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (LazValue::Bool(a), LazValue::Bool(b)) => a == b,
+            (LazValue::Array(a), LazValue::Array(b)) => all_same(a, b),
+            (LazValue::String(a), LazValue::String(b)) => a == b,
+            (LazValue::Tuple(a), LazValue::Tuple(b)) => all_same(a, b),
+            (LazValue::Record(a), LazValue::Record(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|((a_name, a), (b_name, b))| a_name == b_name && a.same(b))
+            }
+            (LazValue::Matrix(a), LazValue::Matrix(b)) => {
+                a.width == b.width && a.height == b.height
+                    && a.data.iter().map(|x| x.to_bits()).eq(b.data.iter().map(|x| x.to_bits()))
+            }
+            (LazValue::Bytes(a), LazValue::Bytes(b)) => a[..] == b[..],
+            (LazValue::UnsignedArray(a), LazValue::UnsignedArray(b)) => a[..] == b[..],
+            (LazValue::SignedArray(a), LazValue::SignedArray(b)) => a[..] == b[..],
+            (LazValue::Lazy(a), LazValue::Lazy(b)) => {
+                // Compared as thin pointers, as vtables of the same type can differ
+                Arc::as_ptr(&a.source) as *const u8 == Arc::as_ptr(&b.source) as *const u8 && a.range == b.range
+            }
+            _ => false,
+        }
+    }
+
+    /// Any number as a float. Large integers lose precision
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            LazValue::Byte(x) => Some(x as f64),
+            LazValue::Unsigned(x) => Some(x as f64),
+            LazValue::Signed(x) => Some(x as f64),
+            LazValue::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Any integer that fits in a u64
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            LazValue::Byte(x) => Some(x as u64),
+            LazValue::Unsigned(x) => Some(x),
+            LazValue::Signed(x) if x >= 0 => Some(x as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        if let LazValue::Bool(b) = *self { Some(b) } else { None }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        if let LazValue::String(ref s) = *self { Some(s) } else { None }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&LazValue> {
+        if let LazValue::Record(ref fields) = *self {
+            fields.iter().find(|(field_name, _)| field_name == name).map(|(_, x)| x)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the contents of a byte array, no matter which representation it uses. Only copies if
+    /// the value is an Array of Bytes. Lazy arrays have to be read first
+    pub fn as_bytes(&self) -> Option<SharedSlice<u8>> {
+        match self {
+            LazValue::Bytes(bytes) => Some(bytes.clone()),
+            LazValue::Array(elems) => {
+                elems.iter()
+                    .map(|x| if let LazValue::Byte(b) = x { Some(*b) } else { None })
+                    .collect::<Option<Vec<u8>>>()
+                    .map(|x| x.into())
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts the compact array representations into an Array of elements. Lazy arrays have to
+    /// be read first
+    pub fn to_elements(&self) -> Option<Vec<LazValue>> {
+        match self {
+            LazValue::Array(elems) => Some(elems.clone()),
+            LazValue::Bytes(bytes) => Some(bytes.iter().map(|&x| LazValue::Byte(x)).collect()),
+            LazValue::UnsignedArray(xs) => Some(xs.iter().map(|&x| LazValue::Unsigned(x)).collect()),
+            LazValue::SignedArray(xs) => Some(xs.iter().map(|&x| LazValue::Signed(x)).collect()),
+            _ => None,
+        }
+    }
+
+    /// Approximate size of the data in bytes, without the overhead of the representation. Shared
+    /// slices count their length, even if the data is also used by other values. Lazy arrays
+    /// don't hold their data, so they count as empty
+    pub fn size(&self) -> usize {
+        match self {
+            LazValue::Byte(_) | LazValue::Bool(_) => 1,
+            LazValue::Char(_) => 4,
+            LazValue::Unsigned(_) | LazValue::Signed(_) | LazValue::Float(_) => 8,
+            LazValue::Array(elems) | LazValue::Tuple(elems) => elems.iter().map(|x| x.size()).sum(),
+            LazValue::String(s) => s.len(),
+            LazValue::Record(fields) => fields.iter().map(|(name, x)| name.len() + x.size()).sum(),
+            LazValue::Matrix(m) => m.data.len() * std::mem::size_of::<f32>(),
+            LazValue::Bytes(xs) => xs.len(),
+            LazValue::UnsignedArray(xs) => xs.len() * std::mem::size_of::<u64>(),
+            LazValue::SignedArray(xs) => xs.len() * std::mem::size_of::<i64>(),
+            LazValue::Lazy(_) => 0,
+        }
+    }
+
+    /// Reads lazy arrays, other values are returned as they are
+    pub fn materialize(self) -> Result<LazValue, LazError> {
+        match self {
+            LazValue::Lazy(xs) => xs.read(),
+            x => Ok(x),
+        }
+    }
+
+    /// The inverse of to_elements: elements that are all bytes, all unsigned or all signed are
+    /// packed into the compact representation, anything else becomes an Array
+    pub fn from_elements(elems: Vec<LazValue>) -> LazValue {
+        fn collect<T>(elems: &[LazValue], get: fn(&LazValue) -> Option<T>) -> Option<Vec<T>> {
+            elems.iter().map(get).collect()
+        }
+
+        let packed = match elems.first() {
+            Some(LazValue::Byte(_)) => {
+                collect(&elems, |x| if let LazValue::Byte(b) = *x { Some(b) } else { None })
+                    .map(|xs| LazValue::Bytes(xs.into()))
+            }
+            Some(LazValue::Unsigned(_)) => {
+                collect(&elems, |x| if let LazValue::Unsigned(u) = *x { Some(u) } else { None })
+                    .map(|xs| LazValue::UnsignedArray(xs.into()))
+            }
+            Some(LazValue::Signed(_)) => {
+                collect(&elems, |x| if let LazValue::Signed(i) = *x { Some(i) } else { None })
+                    .map(|xs| LazValue::SignedArray(xs.into()))
+            }
+            _ => None,
+        };
+        packed.unwrap_or(LazValue::Array(elems))
+    }
+}
+
+impl From<u8> for LazValue {
+    fn from(x: u8) -> LazValue { LazValue::Byte(x) }
+}
+
+impl From<u64> for LazValue {
+    fn from(x: u64) -> LazValue { LazValue::Unsigned(x) }
+}
+
+impl From<i64> for LazValue {
+    fn from(x: i64) -> LazValue { LazValue::Signed(x) }
+}
+
+impl From<f64> for LazValue {
+    fn from(x: f64) -> LazValue { LazValue::Float(x) }
+}
+
+impl From<bool> for LazValue {
+    fn from(x: bool) -> LazValue { LazValue::Bool(x) }
+}
+
+impl From<String> for LazValue {
+    fn from(x: String) -> LazValue { LazValue::String(x) }
+}
+
+impl From<Matrix> for LazValue {
+    fn from(x: Matrix) -> LazValue { LazValue::Matrix(x) }
+}
+
+// Arrays longer than this are cut off when displayed
+const DISPLAY_MAX_ELEMS: usize = 16;
+
+fn display_list<T, I>(f: &mut std::fmt::Formatter<'_>, open: &str, close: &str, elems: I) -> std::fmt::Result
+where
+    T: std::fmt::Display,
+    I: ExactSizeIterator<Item = T>,
+{
+    let len = elems.len();
+    write!(f, "{}", open)?;
+    for (i, elem) in elems.take(DISPLAY_MAX_ELEMS).enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", elem)?;
+    }
+    if len > DISPLAY_MAX_ELEMS {
+        write!(f, ", ... ({} total)", len)?;
+    }
+    write!(f, "{}", close)
+}
+
+impl std::fmt::Display for LazValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LazValue::Byte(x) => write!(f, "0x{:02x}", x),
+            LazValue::Char(x) => write!(f, "{:?}", x),
+            LazValue::Unsigned(x) => write!(f, "{}", x),
+            LazValue::Signed(x) => write!(f, "{}", x),
+            LazValue::Float(x) => write!(f, "{}", x),
+            LazValue::Bool(x) => write!(f, "{}", x),
+            LazValue::Array(elems) => display_list(f, "[", "]", elems.iter()),
+            LazValue::String(x) => write!(f, "{:?}", x),
+            LazValue::Tuple(elems) => display_list(f, "(", ")", elems.iter()),
+            LazValue::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, x)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, x)?;
+                }
+                write!(f, "}}")
+            }
+            LazValue::Matrix(m) => write!(f, "<{}x{} matrix>", m.width, m.height),
+            LazValue::Bytes(xs) => display_list(f, "[", "]", xs.iter().map(|&x| LazValue::Byte(x))),
+            LazValue::UnsignedArray(xs) => display_list(f, "[", "]", xs.iter()),
+            LazValue::SignedArray(xs) => display_list(f, "[", "]", xs.iter()),
+            LazValue::Lazy(xs) => write!(f, "<{} lazy {}>", xs.len(), xs.element_type()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LazType {
+    Byte,
+    Char,
+    Unsigned,
+    Signed,
+    Float,
+    Bool,
+
+    Array(Box<LazType>),
+    String,
+    Tuple(Vec<LazType>),
+    Record(Vec<(String, LazType)>),
+    Matrix,
+
+    /// Any of the contained types
+    Union(Vec<LazType>),
+    Any,
+}
+
+impl LazType {
+    /// Any numeric type
+    pub fn num() -> LazType {
+        LazType::Union(vec![LazType::Byte, LazType::Unsigned, LazType::Signed, LazType::Float])
+    }
+
+    /// Any integer type
+    pub fn integer() -> LazType {
+        LazType::Union(vec![LazType::Byte, LazType::Unsigned, LazType::Signed])
+    }
+
+    pub fn array_of(inner: LazType) -> LazType {
+        LazType::Array(Box::new(inner))
+    }
+
+    /// Can every value of type `other` be used where `self` is expected?
+    pub fn accepts(&self, other: &LazType) -> bool {
+        match (self, other) {
+            (LazType::Any, _) => true,
+            // A value typed as Any might be anything, we can only check it at runtime
+            (_, LazType::Any) => true,
+            (_, LazType::Union(others)) => others.iter().all(|o| self.accepts(o)),
+            (LazType::Union(options), _) => options.iter().any(|t| t.accepts(other)),
+            (LazType::Array(a), LazType::Array(b)) => a.accepts(b),
+            (LazType::Tuple(a), LazType::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.accepts(b))
+            }
+            (LazType::Record(a), LazType::Record(b)) => {
+                a.len() == b.len()
+                    && a.iter().zip(b.iter()).all(|((a_name, a), (b_name, b))| a_name == b_name && a.accepts(b))
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl std::fmt::Display for LazType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LazType::Byte => write!(f, "byte"),
+            LazType::Char => write!(f, "char"),
+            LazType::Unsigned => write!(f, "unsigned"),
+            LazType::Signed => write!(f, "signed"),
+            LazType::Float => write!(f, "float"),
+            LazType::Bool => write!(f, "bool"),
+            LazType::Array(inner) => write!(f, "[{}]", inner),
+            LazType::String => write!(f, "string"),
+            LazType::Tuple(elems) => {
+                write!(f, "(")?;
+                for (i, elem) in elems.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, ")")
+            }
+            LazType::Record(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, ty)) in fields.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", name, ty)?;
+                }
+                write!(f, "}}")
+            }
+            LazType::Matrix => write!(f, "matrix"),
+            LazType::Union(options) => {
+                write!(f, "{{")?;
+                for (i, option) in options.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, "|")?;
+                    }
+                    write!(f, "{}", option)?;
+                }
+                write!(f, "}}")
+            }
+            LazType::Any => write!(f, "any"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unions_accept_their_options() {
+        assert!(LazType::num().accepts(&LazType::Byte));
+        assert!(LazType::num().accepts(&LazType::Signed));
+        assert!(LazType::num().accepts(&LazType::num()));
+        assert!(!LazType::num().accepts(&LazType::String));
+        // Only some of the values of a union fit one of its options
+        assert!(!LazType::Unsigned.accepts(&LazType::num()));
+    }
+
+    #[test]
+    fn any_is_checked_at_runtime() {
+        assert!(LazType::Any.accepts(&LazType::String));
+        assert!(LazType::Byte.accepts(&LazType::Any));
+    }
+
+    #[test]
+    fn arrays_accept_arrays_of_accepted_elements() {
+        let nums = LazType::array_of(LazType::num());
+        assert!(nums.accepts(&LazType::array_of(LazType::Byte)));
+        assert!(!nums.accepts(&LazType::array_of(LazType::String)));
+        assert!(!nums.accepts(&LazType::Byte));
+    }
+
+    #[test]
+    fn arrays_are_typed_by_their_first_element() {
+        let array = LazValue::Array(vec![LazValue::Unsigned(1), LazValue::Unsigned(2)]);
+        assert_eq!(array.get_type(), LazType::array_of(LazType::Unsigned));
+        assert_eq!(LazValue::Array(vec![]).get_type(), LazType::array_of(LazType::Any));
+        assert_eq!(array.get_type().to_string(), "[unsigned]");
+        assert_eq!(LazType::Union(vec![LazType::Byte, LazType::Char]).to_string(), "{byte|char}");
+    }
+
+    #[test]
+    fn slices_share_their_data() {
+        let data = SharedSlice::from(vec![1u8, 2, 3, 4, 5]);
+        let middle = data.slice(1..4).unwrap();
+        assert_eq!(&*middle, &[2, 3, 4]);
+        // Ranges are relative to the slice that is sliced
+        assert_eq!(&*middle.slice(1..3).unwrap(), &[3, 4]);
+        assert!(middle.slice(2..4).is_none());
+        let (start, end) = (2, 1);
+        assert!(middle.slice(start..end).is_none());
+    }
+
+    #[test]
+    fn compact_arrays_have_the_element_array_type() {
+        let bytes = LazValue::Bytes(vec![1, 2].into());
+        let elements = LazValue::Array(vec![LazValue::Byte(1), LazValue::Byte(2)]);
+        assert_eq!(bytes.get_type(), elements.get_type());
+        assert_eq!(&*elements.as_bytes().unwrap(), &*bytes.as_bytes().unwrap());
+        assert!(LazValue::Array(vec![LazValue::Unsigned(1)]).as_bytes().is_none());
+
+        let signed = LazValue::SignedArray(vec![-1, 2].into());
+        assert_eq!(signed.get_type(), LazType::array_of(LazType::Signed));
+        assert!(matches!(signed.to_elements().unwrap()[..], [LazValue::Signed(-1), LazValue::Signed(2)]));
+    }
+
+    #[test]
+    fn tuples_and_records_check_every_element() {
+        let pair = LazType::Tuple(vec![LazType::num(), LazType::String]);
+        assert!(pair.accepts(&LazType::Tuple(vec![LazType::Float, LazType::String])));
+        assert!(!pair.accepts(&LazType::Tuple(vec![LazType::Float])));
+
+        let point = LazValue::Record(vec![("x".into(), 1.5.into()), ("y".into(), true.into())]);
+        let point_type = LazType::Record(vec![("x".into(), LazType::num()), ("y".into(), LazType::Bool)]);
+        assert!(point_type.accepts(&point.get_type()));
+        // The field names have to match too
+        let swapped = LazType::Record(vec![("y".into(), LazType::Float), ("x".into(), LazType::Bool)]);
+        assert!(!swapped.accepts(&point.get_type()));
+
+        assert_eq!(point.field("y").and_then(|y| y.as_bool()), Some(true));
+        assert!(point.field("z").is_none());
+        assert_eq!(point.to_string(), "{x: 1.5, y: true}");
+        assert_eq!(point_type.to_string(), "{x: {byte|unsigned|signed|float}, y: bool}");
+    }
+
+    #[test]
+    fn matrices_need_one_value_per_cell() {
+        assert!(Matrix::new(2, 3, vec![0.; 5]).is_none());
+        let m = Matrix::new(2, 2, vec![1., 2., 3., 4.]).unwrap();
+        assert_eq!(m.row(1).unwrap(), &[3., 4.]);
+        assert!(m.row(2).is_none());
+        assert_eq!(LazValue::from(m).to_string(), "<2x2 matrix>");
+    }
+
+    #[test]
+    fn numbers_convert_when_they_fit() {
+        assert_eq!(LazValue::Signed(-3).as_f64(), Some(-3.));
+        assert_eq!(LazValue::Signed(-3).as_u64(), None);
+        assert_eq!(LazValue::Byte(7).as_u64(), Some(7));
+        assert_eq!(LazValue::Float(1.).as_u64(), None);
+    }
+
+    #[test]
+    fn long_arrays_are_cut_off_when_displayed() {
+        let value = LazValue::UnsignedArray((0..20).collect::<Vec<u64>>().into());
+        assert!(value.to_string().ends_with("15, ... (20 total)]"));
+    }
+}
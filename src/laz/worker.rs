@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use crate::laz::types::LazValue;
+use crate::laz::env::{LazEnv, EvalControl};
+use crate::laz::nodes::{OutputID, LazError};
+use crate::laz::registry::NodeRegistry;
+
+/// An evaluation of one output, running on its own thread so it doesn't block the caller
+pub struct EvalJob {
+    pub output: OutputID,
+    generation: u64,
+    control: Arc<EvalControl>,
+    receiver: Receiver<(LazEnv, Result<LazValue, LazError>)>,
+}
+
+impl std::fmt::Debug for EvalJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EvalJob")
+            .field("output", &self.output)
+            .field("generation", &self.generation)
+            .field("progress", &self.progress())
+            .finish()
+    }
+}
+
+impl EvalJob {
+    /// Starts evaluating `output` on a snapshot of `env`
+    pub fn start(env: &LazEnv, output: OutputID, registry: &NodeRegistry) -> Result<EvalJob, LazError> {
+        let mut snapshot = env.snapshot(output.node, registry)?;
+        let control = Arc::new(EvalControl::default());
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_control = control.clone();
+        std::thread::spawn(move || {
+            let result = snapshot.evaluate_output_with(output, &thread_control);
+            // Fails if the job has been dropped, then nobody wants the result anyway
+            let _ = sender.send((snapshot, result));
+        });
+
+        Ok(EvalJob {
+            output,
+            generation: env.generation(),
+            control,
+            receiver,
+        })
+    }
+
+    /// How many nodes have finished, and how many have to be evaluated in total
+    pub fn progress(&self) -> (usize, usize) {
+        self.control.progress()
+    }
+
+    /// Stops the job before the next node starts. A node that's already running isn't
+    /// interrupted, but its result is thrown away if the job is dropped
+    pub fn cancel(&self) {
+        self.control.cancel();
+    }
+
+    /// Whether `env` has changed since the job started, so the result would be outdated
+    pub fn is_stale(&self, env: &LazEnv) -> bool {
+        env.generation() != self.generation
+    }
+
+    /// The result, if the job has finished. Everything evaluated along the way is added to the
+    /// cache of `env`, unless it has changed since the job started
+    pub fn poll(&self, env: &mut LazEnv) -> Option<Result<LazValue, LazError>> {
+        match self.receiver.try_recv() {
+            Ok((snapshot, result)) => {
+                if !self.is_stale(env) {
+                    env.merge_snapshot(snapshot);
+                }
+                Some(result)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(LazError::Other("Evaluation thread panicked".into()))),
+        }
+    }
+}
+
+impl Drop for EvalJob {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::laz::nodes::{ID, InputID, ConstantNode, ReadFileNode};
+    use crate::laz::byte_nodes::ReverseNode;
+
+    fn output(node: ID) -> OutputID {
+        OutputID { node, outport: 0 }
+    }
+
+    fn finish(job: EvalJob, env: &mut LazEnv) -> Result<LazValue, LazError> {
+        loop {
+            if let Some(result) = job.poll(env) {
+                return result;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn evaluating_after_a_no_op_edit_hits_the_caches() {
+        let path = std::env::temp_dir().join(format!("laz-{}-worker", std::process::id()));
+        std::fs::write(&path, b"abc").unwrap();
+        let path_value = || LazValue::String(path.to_string_lossy().into_owned());
+
+        let registry = NodeRegistry::builtin();
+        let mut env = LazEnv::default();
+        let name = env.add_node(Box::new(ConstantNode { value: path_value() })).unwrap();
+        let read = env.add_node(Box::new(ReadFileNode::new(output(name)))).unwrap();
+        let reverse = env.add_node(Box::new(ReverseNode { input_bytes: output(read) })).unwrap();
+        let evaluate = |env: &mut LazEnv| {
+            let result = finish(EvalJob::start(env, output(reverse), &registry).unwrap(), env).unwrap();
+            assert_eq!(&*result.as_bytes().unwrap(), b"cba");
+        };
+
+        evaluate(&mut env);
+        assert_eq!(env.profile(read).unwrap().runs, 1);
+
+        // Connecting the same output again only invalidates the reverse, the read is cached
+        env.set_input(output(read), InputID { node: reverse, inport: 0 }).unwrap();
+        evaluate(&mut env);
+        let profile = env.profile(read).unwrap();
+        assert_eq!((profile.runs, profile.cache_hits), (1, 1));
+        assert_eq!(env.profile(reverse).unwrap().runs, 2);
+
+        // Setting the same file name again reruns the read, which reuses the file it has open
+        env.replace_node(name, Box::new(ConstantNode { value: path_value() })).unwrap();
+        evaluate(&mut env);
+        let profile = env.profile(read).unwrap();
+        assert_eq!(profile.runs, 2);
+        assert_eq!((profile.node_cache.hits, profile.node_cache.misses), (1, 1));
+        assert!(crate::laz::dot::to_dot(&env).contains("2 runs"));
+        std::fs::remove_file(&path).unwrap();
+
+        env.reset_profiles();
+        assert!(env.profile(read).is_none());
+        assert_eq!(env.cache_hit_rate(), None);
+    }
+}
@@ -5,7 +5,7 @@ use anyhow::Result;
 
 use winit::{
     dpi::LogicalSize,
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent, ElementState},
+    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent, ElementState, MouseButton, ModifiersState},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
@@ -16,18 +16,42 @@ mod render;
 mod state;
 mod easing;
 mod laz;
+mod editor;
 
 use easing::Easing;
 
+// Where Ctrl+D dumps the graph, relative to the working directory
+const DOT_PATH: &str = "graph.dot";
+// Where Ctrl+S saves the graph
+const GRAPH_PATH: &str = "graph.ron";
+
 fn main() -> Result<()> {
-    let (mut env, sum_id) = laz::example_env();
+    // A saved graph can be given on the command line, otherwise we start with the example
+    let env = match std::env::args().nth(1) {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path)?;
+            laz::env::LazEnv::load(&text, &laz::registry::NodeRegistry::builtin())?
+        }
+        None => {
+            let (mut env, sum_id) = laz::example_env()?;
 
-    println!("{:?}", env.evaluate_node(sum_id));
+            match env.evaluate_node(sum_id) {
+                Ok(values) => {
+                    for value in values {
+                        println!("{}", value);
+                    }
+                }
+                // anyhow's Debug prints the whole chain of causes
+                Err(e) => println!("Error: {:?}", anyhow::Error::from(e)),
+            }
+            env
+        }
+    };
 
-    block_on(run())
+    block_on(run(env))
 }
 
-async fn run() -> Result<()> {
+async fn run(env: laz::env::LazEnv) -> Result<()> {
     pretty_env_logger::init();
     let e_loop = EventLoop::new();
 
@@ -41,7 +65,7 @@ async fn run() -> Result<()> {
     let mut render = render::Render::new(&win).await?;
 
     let size = win.inner_size();
-    let mut e_state = state::State::<easing::SinEasing>::new(size.width, size.height);
+    let mut e_state = state::State::<easing::SinEasing>::new(size.width, size.height, env);
 
     let mut last_t = std::time::Instant::now();
     let mut last_fps = std::time::Instant::now();
@@ -50,6 +74,8 @@ async fn run() -> Result<()> {
 
     let mut section_state = 0; // 0 = hidden, 1 = half, 2 = max
 
+    let mut modifiers = ModifiersState::empty();
+
     e_loop.run(move |ev, _elwt, cf| match ev {
         Event::WindowEvent {
             window_id,
@@ -104,6 +130,113 @@ async fn run() -> Result<()> {
             } => {
                 info!("State: {:?}", e_state);
             }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Delete) | Some(VirtualKeyCode::Back),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if section_state != 0 => {
+                e_state.editor.delete_selected(&mut e_state.env);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Z),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if section_state != 0 && modifiers.ctrl() => {
+                e_state.editor.undo(&mut e_state.env);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Y),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if section_state != 0 && modifiers.ctrl() => {
+                e_state.editor.redo(&mut e_state.env);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if section_state != 0 && modifiers.ctrl() => {
+                e_state.collapse_selected();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if modifiers.ctrl() => {
+                e_state.cancel_evaluation();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if modifiers.ctrl() => {
+                e_state.toggle_profile();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::R),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if modifiers.ctrl() => {
+                e_state.reset_profiles();
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::D),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if modifiers.ctrl() => {
+                let path = std::path::Path::new(DOT_PATH);
+                match e_state.export_dot(path) {
+                    Ok(()) => info!("Wrote graph to {}", path.display()),
+                    Err(e) => warn!("Could not write graph to {}: {}", path.display(), e),
+                }
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::S),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if modifiers.ctrl() => {
+                let path = std::path::Path::new(GRAPH_PATH);
+                match e_state.save_graph(path) {
+                    Ok(()) => info!("Saved graph to {}", path.display()),
+                    Err(e) => warn!("Could not save graph to {}: {}", path.display(), e),
+                }
+            }
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -115,6 +248,22 @@ async fn run() -> Result<()> {
             } => {
                 info!("Pressed {:?}", k);
             }
+            WindowEvent::DroppedFile(path) if section_state != 0 => {
+                e_state.drop_file(&path);
+            }
+            WindowEvent::ModifiersChanged(new_modifiers) => {
+                modifiers = new_modifiers;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                e_state.mouse_moved(position);
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                e_state.mouse_input(state);
+            }
             WindowEvent::Resized(new_size)
             | WindowEvent::ScaleFactorChanged {
                 new_inner_size: &mut new_size,
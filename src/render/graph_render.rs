@@ -0,0 +1,189 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use anyhow::Result;
+
+use wgpu::util::DeviceExt;
+
+use crate::editor::{BoxInstance, WireVertex, GraphGeometry};
+
+// Same as outline_radius in menu.vert. The graph is clipped to inside the outline
+const SECTION_OUTLINE: u32 = 10;
+
+pub(super) struct GraphRender {
+    box_pipeline: wgpu::RenderPipeline,
+    wire_pipeline: wgpu::RenderPipeline,
+}
+
+impl GraphRender {
+    pub(super) async fn new(device: &wgpu::Device, screen_layout_bind_group_layout: &wgpu::BindGroupLayout, format: wgpu::TextureFormat) -> Result<Self> {
+        let box_vs_desc: wgpu::ShaderModuleDescriptor =
+            wgpu::include_spirv!("shaders/compiled/graph_box.vert.spv");
+
+        let box_fs_desc: wgpu::ShaderModuleDescriptor =
+            wgpu::include_spirv!("shaders/compiled/graph_box.frag.spv");
+
+        let wire_vs_desc: wgpu::ShaderModuleDescriptor =
+            wgpu::include_spirv!("shaders/compiled/graph_wire.vert.spv");
+
+        let wire_fs_desc: wgpu::ShaderModuleDescriptor =
+            wgpu::include_spirv!("shaders/compiled/graph_wire.frag.spv");
+
+        let box_vs_mod = device.create_shader_module(&box_vs_desc);
+        let box_fs_mod = device.create_shader_module(&box_fs_desc);
+        let wire_vs_mod = device.create_shader_module(&wire_vs_desc);
+        let wire_fs_mod = device.create_shader_module(&wire_fs_desc);
+
+        let blending = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::Zero,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Graph render pipeline layout"),
+                bind_group_layouts: &[screen_layout_bind_group_layout],
+                push_constant_ranges: &[],
+            }
+        );
+
+        let targets = [wgpu::ColorTargetState {
+            blend: Some(blending),
+            format,
+            write_mask: wgpu::ColorWrite::ALL,
+        }];
+
+        // Geometry is built in window coordinates on the CPU, so there's nothing to cull
+        let primitive = wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            clamp_depth: false,
+            conservative: false,
+        };
+
+        let multisample = wgpu::MultisampleState {
+            count: 1,
+            mask: !1,
+            alpha_to_coverage_enabled: false,
+        };
+
+        let box_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Graph box render pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &box_vs_mod,
+                entry_point: "main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<BoxInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x3, 3 => Float32, 4 => Float32],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &box_fs_mod,
+                entry_point: "main",
+                targets: &targets,
+            }),
+            primitive,
+            depth_stencil: None,
+            multisample,
+        });
+
+        let wire_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Graph wire render pipeline"),
+            layout: Some(&render_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &wire_vs_mod,
+                entry_point: "main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<WireVertex>() as wgpu::BufferAddress,
+                    step_mode: wgpu::InputStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x3],
+                }],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &wire_fs_mod,
+                entry_point: "main",
+                targets: &targets,
+            }),
+            primitive,
+            depth_stencil: None,
+            multisample,
+        });
+
+        Ok(GraphRender {
+            box_pipeline,
+            wire_pipeline,
+        })
+    }
+
+    /// Draws the graph, clipped to the menu section
+    pub(super) fn render(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        frame: &wgpu::SwapChainTexture,
+        screen_layout_bind_group: &wgpu::BindGroup,
+        screen_layout: &crate::state::ScreenLayout,
+        geometry: &GraphGeometry,
+    ) {
+        let (width, height) = (screen_layout.width, screen_layout.height);
+        let section_top = (height as f32 * (1. - screen_layout.section_height)).round().max(0.) as u32 + SECTION_OUTLINE;
+        if section_top >= height || width == 0 {
+            return;
+        }
+
+        // Buffers have to outlive the render pass. Empty ones are skipped, as wgpu doesn't allow
+        // zero sized vertex buffers
+        let create_buffer = |label, contents: &[u8]| {
+            if contents.is_empty() {
+                return None;
+            }
+            Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsage::VERTEX,
+            }))
+        };
+        let box_buffer = create_buffer("Graph box buffer", bytemuck::cast_slice(&geometry.boxes));
+        let wire_buffer = create_buffer("Graph wire buffer", bytemuck::cast_slice(&geometry.wires));
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: &frame.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_scissor_rect(0, section_top, width, height - section_top);
+        render_pass.set_bind_group(0, screen_layout_bind_group, &[]);
+
+        if let Some(ref wire_buffer) = wire_buffer {
+            render_pass.set_pipeline(&self.wire_pipeline);
+            render_pass.set_vertex_buffer(0, wire_buffer.slice(..));
+            render_pass.draw(0..geometry.wires.len() as u32, 0..1);
+        }
+
+        if let Some(ref box_buffer) = box_buffer {
+            render_pass.set_pipeline(&self.box_pipeline);
+            render_pass.set_vertex_buffer(0, box_buffer.slice(..));
+            render_pass.draw(0..6, 0..geometry.boxes.len() as u32);
+        }
+    }
+}
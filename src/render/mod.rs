@@ -14,6 +14,9 @@ use menu_render::MenuRender;
 mod data_render;
 use data_render::DataRender;
 
+mod graph_render;
+use graph_render::GraphRender;
+
 
 pub struct Render {
     pub size: PhysicalSize<u32>,
@@ -30,6 +33,8 @@ pub struct Render {
     menu_render: MenuRender,
 
     data_render: DataRender,
+
+    graph_render: GraphRender,
 }
 
 impl Render {
@@ -82,6 +87,7 @@ impl Render {
 
         let menu_render = MenuRender::new(&device, &screen_layout_bind_group_layout, sc_desc.format).await?;
         let data_render = DataRender::new(&device, &screen_layout_bind_group_layout, sc_desc.format).await?;
+        let graph_render = GraphRender::new(&device, &screen_layout_bind_group_layout, sc_desc.format).await?;
 
         Ok(Render {
             surface,
@@ -97,6 +103,7 @@ impl Render {
 
             menu_render,
             data_render,
+            graph_render,
         })
     }
 
@@ -173,6 +180,7 @@ impl Render {
 
         self.data_render.render(&mut encoder, &mut self.queue, &frame, &self.screen_layout_bind_group, &render_state.render_data);
         self.menu_render.render(&mut encoder, &frame, &self.screen_layout_bind_group);
+        self.graph_render.render(&mut encoder, &self.device, &frame, &self.screen_layout_bind_group, &render_state.screen_layout, &render_state.graph);
 
         self.queue.submit(vec![encoder.finish()]);
 
@@ -0,0 +1,27 @@
+#version 450
+
+#include <colorspace.glsl>
+
+layout(location=1) in vec2 v_pos;
+layout(location=2) in vec3 v_color;
+layout(location=3) in float border_radius;
+layout(location=4) in float outline_radius;
+layout(location=5) in vec2 v_half_size;
+
+layout(location=0) out vec4 f_color;
+
+float aa_bias = 1;
+
+void main() {
+    // Signed distance to the edge of the rounded box, negative inside
+    vec2 q = abs(v_pos) - v_half_size + border_radius;
+    float dist = length(max(q, 0)) + min(max(q.x, q.y), 0) - border_radius;
+
+    float alpha = clamp(0.5 - dist * aa_bias, 0, 1);
+    float outline = clamp(0.5 + (dist + outline_radius) * aa_bias, 0, 1);
+
+    vec4 c_outline = vec4(lab2rgb(0.1 * v_color), alpha);
+    vec4 c_inline = vec4(lab2rgb(v_color), alpha);
+
+    f_color = outline * c_outline + (1 - outline) * c_inline;
+}
@@ -0,0 +1,50 @@
+#version 450
+
+#include <colorspace.glsl>
+
+layout(set=0, binding=0) uniform RenderState {
+    int width;
+    int height;
+    float t;
+    float section_height;
+};
+
+const int i2j[6] = {0, 1, 2, 2, 3, 0};
+
+const vec2 corners[4] = vec2[4](
+    vec2(0, 0),
+    vec2(1, 0),
+    vec2(1, 1),
+    vec2(0, 1)
+);
+
+// Per instance. Position and size are in pixels, from the top left of the window
+layout(location=0) in vec2 i_pos;
+layout(location=1) in vec2 i_size;
+layout(location=2) in vec3 i_color;
+layout(location=3) in float i_radius;
+layout(location=4) in float i_outline;
+
+// Pixels from the center of the box
+layout(location=1) out vec2 v_pos;
+layout(location=2) out vec3 v_color;
+layout(location=3) out float border_radius;
+layout(location=4) out float outline_radius;
+layout(location=5) out vec2 v_half_size;
+
+void main() {
+    vec2 corner = corners[i2j[gl_VertexIndex]];
+
+    vec2 pxy = i_pos + corner * i_size;
+
+    v_half_size = i_size / 2;
+    v_pos = pxy - i_pos - v_half_size;
+
+    v_color = rgb2lab(i_color);
+
+    border_radius = i_radius;
+    outline_radius = i_outline;
+
+    vec2 ndc = pxy / vec2(width, height) * 2 - 1;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+}
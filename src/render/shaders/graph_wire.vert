@@ -0,0 +1,21 @@
+#version 450
+
+layout(set=0, binding=0) uniform RenderState {
+    int width;
+    int height;
+    float t;
+    float section_height;
+};
+
+// In pixels, from the top left of the window
+layout(location=0) in vec2 i_pos;
+layout(location=1) in vec3 i_color;
+
+layout(location=2) out vec3 v_color;
+
+void main() {
+    v_color = i_color;
+
+    vec2 ndc = i_pos / vec2(width, height) * 2 - 1;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+}
@@ -1,41 +1,204 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
 use crate::easing;
+use crate::editor::{Editor, GraphGeometry};
+use crate::laz::env::LazEnv;
+use crate::laz::dot;
+use crate::laz::types::LazValue;
+use crate::laz::nodes::LazError;
+use crate::laz::registry::NodeRegistry;
+use crate::laz::worker::EvalJob;
+
+// Seconds between checking for changed files
+const REFRESH_INTERVAL: f64 = 0.5;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ScreenLayout {
-    width: u32,
-    height: u32,
+    pub width: u32,
+    pub height: u32,
 
-    t: f32,
-    section_height: f32,
+    pub t: f32,
+    pub section_height: f32,
 }
 
 #[derive(Debug, Clone)]
 pub struct RenderState {
     pub screen_layout: ScreenLayout,
     pub render_data: [[f32; 256]; 256],
+    pub graph: GraphGeometry,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct State<E: easing::Easing> {
     pub size: (u32, u32),
     pub t: f64,
 
     pub easing: E,
+
+    pub env: LazEnv,
+    pub editor: Editor,
+    // In pixels from the top left of the window
+    mouse: (f32, f32),
+    registry: NodeRegistry,
+    // When env was last checked for external changes
+    last_refresh: f64,
+    // Evaluation of the displayed output running in the background
+    job: Option<EvalJob>,
+    // Generation of env when the last evaluation failed or was cancelled, so we don't retry until
+    // something changes
+    failed_generation: Option<u64>,
+    // Last successfully evaluated displayed output of env
+    render_data: Box<[[f32; 256]; 256]>,
+    // So we don't log the same error every frame
+    last_render_error: Option<String>,
+    show_profile: bool,
 }
 
 impl <E: easing::Easing> State<E> {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, env: LazEnv) -> Self {
         State {
             size: (width, height),
             t: 0.,
             easing: E::new_with_value(0.),
+            env,
+            editor: Editor::default(),
+            mouse: (0., 0.),
+            registry: NodeRegistry::builtin(),
+            last_refresh: 0.,
+            job: None,
+            failed_generation: None,
+            render_data: Box::new([[0.0; 256]; 256]),
+            last_render_error: None,
+            show_profile: false,
         }
     }
 
     pub fn step(&mut self, dt: f64) {
         self.t += dt;
         self.easing.step(dt);
+
+        if self.t - self.last_refresh > REFRESH_INTERVAL {
+            self.last_refresh = self.t;
+            self.env.refresh();
+        }
+
+        self.editor.layout(&self.env);
+        self.update_render_data();
+    }
+
+    /// Reevaluates the displayed output in the background if it's changed. Until the new result
+    /// arrives, and on errors, the previous data is kept
+    pub fn update_render_data(&mut self) {
+        let displayed = self.env.displayed();
+
+        // A result for an old version of the graph is useless, so we start over
+        if let Some(ref job) = self.job {
+            if job.is_stale(&self.env) || Some(job.output) != displayed {
+                self.job = None;
+            }
+        }
+
+        if let Some(ref job) = self.job {
+            let result = match job.poll(&mut self.env) {
+                Some(result) => result,
+                None => return,
+            };
+            self.job = None;
+            if result.is_err() {
+                self.failed_generation = Some(self.env.generation());
+            }
+            self.show_result(result);
+            return;
+        }
+
+        let displayed = match displayed {
+            Some(displayed) => displayed,
+            None => return,
+        };
+        if !self.env.is_dirty(displayed.node) || self.failed_generation == Some(self.env.generation()) {
+            return;
+        }
+
+        match EvalJob::start(&self.env, displayed, &self.registry) {
+            Ok(job) => self.job = Some(job),
+            Err(e) => {
+                self.failed_generation = Some(self.env.generation());
+                self.show_result(Err(e));
+            }
+        }
+    }
+
+    fn show_result(&mut self, result: Result<LazValue, LazError>) {
+        let failing_node = result.as_ref().err().and_then(|e| e.failing_node());
+        let error = match result {
+            Ok(LazValue::Matrix(m)) if m.width == 256 && m.height == 256 => {
+                for (y, row) in self.render_data.iter_mut().enumerate() {
+                    // Unwrap is fine, we checked the size
+                    row.copy_from_slice(m.row(y).unwrap());
+                }
+                None
+            }
+            Ok(value) => Some(format!("Displayed value is not a 256x256 matrix, but {}", value.get_type())),
+            Err(e) => {
+                let cause = anyhow::Error::from(e.root_cause().clone());
+                match e.failing_node() {
+                    Some(node) => {
+                        // The chain shows how the displayed node depends on the one that failed
+                        let chain = e.node_chain().iter().map(|id| id.to_string()).collect::<Vec<_>>().join(" <- ");
+                        Some(format!("Could not evaluate displayed value, node {} failed ({}): {:#}", node, chain, cause))
+                    }
+                    None => Some(format!("Could not evaluate displayed value: {:#}", cause)),
+                }
+            }
+        };
+        self.editor.set_failed(failing_node);
+
+        if error != self.last_render_error {
+            if let Some(ref error) = error {
+                warn!("{}", error);
+            }
+            self.last_render_error = error;
+        }
+    }
+
+    /// Nodes evaluated and total nodes to evaluate, if an evaluation is running
+    pub fn evaluation_progress(&self) -> Option<(usize, usize)> {
+        self.job.as_ref().map(|job| job.progress())
+    }
+
+    /// Stops the running evaluation. It isn't restarted until the graph changes
+    pub fn cancel_evaluation(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+            self.failed_generation = Some(self.env.generation());
+            self.show_result(Err(LazError::Cancelled));
+        }
+    }
+
+    pub fn collapse_selected(&mut self) {
+        self.editor.collapse_selected(&mut self.env, &self.registry);
+    }
+
+    pub fn toggle_profile(&mut self) {
+        self.show_profile = !self.show_profile;
+    }
+
+    /// Starts profiling over, for example to measure only what happens after an edit
+    pub fn reset_profiles(&mut self) {
+        self.env.reset_profiles();
+    }
+
+    /// Writes the graph as RON, which can be loaded by passing the file on the command line
+    pub fn save_graph(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.env.save()?)?;
+        Ok(())
+    }
+
+    /// Writes the graph as Graphviz DOT, with how each node's last evaluation went
+    pub fn export_dot(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, dot::to_dot(&self.env))
     }
 
     pub fn get_layout(&self) -> ScreenLayout {
@@ -50,26 +213,73 @@ impl <E: easing::Easing> State<E> {
     pub fn get_render_state(&self) -> RenderState {
         let screen_layout = self.get_layout();
         let render_data = self.get_render_data();
+        let mut graph = self.editor.geometry(&self.env, (0., self.section_top()));
+        if let Some((done, total)) = self.evaluation_progress() {
+            let fraction = if total == 0 { 0. } else { done as f32 / total as f32 };
+            graph.add_progress_bar((0., self.section_top()), self.size.0 as f32, fraction);
+        }
+        if self.show_profile {
+            graph.add_profile(&self.env, (0., self.section_top()), self.size.0 as f32);
+        }
         RenderState {
             screen_layout,
             render_data,
+            graph,
         }
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        self.size.0 = new_size.width;
-        self.size.1 = new_size.height;
+    // Where the menu section starts, in pixels from the top of the window
+    fn section_top(&self) -> f32 {
+        self.size.1 as f32 * (1. - self.easing.get() as f32)
     }
 
-    pub fn get_render_data(&self) -> [[f32; 256]; 256] {
-        const DATA: &[u8] = include_bytes!("render/mod.rs");
+    // The mouse position relative to the editor
+    fn editor_mouse(&self) -> (f32, f32) {
+        (self.mouse.0, self.mouse.1 - self.section_top())
+    }
 
-        let mut out = [[0.0; 256]; 256];
+    pub fn mouse_moved(&mut self, position: winit::dpi::PhysicalPosition<f64>) {
+        self.mouse = (position.x as f32, position.y as f32);
+        self.editor.mouse_moved(self.editor_mouse());
+    }
 
-        for (&first, &second) in DATA.iter().zip(DATA.iter().skip(1)) {
-            out[first as usize][second as usize] += 1.0 / (DATA.len() as f32);
+    /// Clicks above the menu section are ignored, but releases aren't so drags can end anywhere
+    pub fn mouse_input(&mut self, state: winit::event::ElementState) {
+        let pos = self.editor_mouse();
+        match state {
+            winit::event::ElementState::Pressed if pos.1 >= 0. => {
+                let selected = self.env.selected();
+                self.editor.mouse_pressed(&mut self.env, pos);
+                if self.env.selected() != selected {
+                    self.describe_selected();
+                }
+            }
+            winit::event::ElementState::Pressed => {}
+            winit::event::ElementState::Released => self.editor.mouse_released(&mut self.env, pos),
         }
+    }
+
+    // The editor only shows the kind of a node, so what it does is logged when it's selected
+    fn describe_selected(&self) {
+        let node = match self.env.selected().and_then(|id| self.env.get_node(id)) {
+            Some(node) => node,
+            None => return,
+        };
+        if let Ok(node_type) = self.registry.get(node.kind()) {
+            info!("{} ({}): {}", node_type.kind, node_type.category, node_type.description);
+        }
+    }
+
+    pub fn drop_file(&mut self, path: &std::path::Path) {
+        self.editor.drop_file(&mut self.env, &self.registry, path);
+    }
 
-        out
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.size.0 = new_size.width;
+        self.size.1 = new_size.height;
+    }
+
+    pub fn get_render_data(&self) -> [[f32; 256]; 256] {
+        *self.render_data
     }
 }